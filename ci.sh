@@ -353,6 +353,10 @@ if [ "$PROVIDER_NAME" = "cargo-check" ]; then
     RUST_BACKTRACE=1 cargo check --features="jwt-svid-authenticator"
     RUST_BACKTRACE=1 cargo check --features="all-authenticators"
 
+    RUST_BACKTRACE=1 cargo check --features="tls-listener"
+    RUST_BACKTRACE=1 cargo check --features="vsock-listener"
+    RUST_BACKTRACE=1 cargo check --features="http-gateway"
+
     exit 0
 fi
 
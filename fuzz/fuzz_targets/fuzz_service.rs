@@ -5,7 +5,10 @@
 use arbitrary::Arbitrary;
 use lazy_static::lazy_static;
 use libfuzzer_sys::fuzz_target;
-use parsec_service::front::{front_end::FrontEndHandler, listener::Connection};
+use parsec_service::front::{
+    front_end::FrontEndHandler,
+    listener::{Connection, Protocol},
+};
 use parsec_service::utils::{config::ServiceConfig, ServiceBuilder};
 use std::cmp;
 use std::io::{Read, Result, Write};
@@ -53,6 +56,8 @@ fuzz_target!(|stream: MockStream| {
     FRONT_END_HANDLER.handle_request(Connection {
         stream: Box::from(stream),
         metadata: None,
+        permitted_authenticators: None,
+        protocol: Protocol::Parsec,
     });
 });
 
@@ -0,0 +1,306 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Control group authenticator
+//!
+//! The `CgroupAuthenticator` identifies the connecting process by its control group, read from
+//! `/proc/<pid>/cgroup`: with systemd, the control group of a service is named after its unit, and
+//! container runtimes give each container its own control group. Keys can then be isolated per
+//! service without allocating a UID to each of them.
+//!
+//! The path of the control group in the unified (v2) hierarchy is used if there is one, the path
+//! in the systemd (v1) hierarchy otherwise. It is matched against the configured patterns, in
+//! order, and the name of the first matching pattern, in which the groups captured by the pattern
+//! are expanded, is the application name. Processes whose control group matches none of the
+//! patterns are not authenticated.
+//!
+//! The applications share their namespace with the other authenticators using Unix peer
+//! credentials. Names which the Unix peer credentials or peer executable authenticators could
+//! give, a UID, optionally followed by `:` and a path, or a group identity, `group:<gid>`, are
+//! rejected so that a control group can not take over the keys of those applications.
+//!
+//! Like the Unix peer credentials authenticator, the UID declared in the request must be the one
+//! found in the peer credentials, and the authenticator is used for requests with the Unix peer
+//! credentials authentication type. The start time of the process is checked to detect PID reuse.
+
+use super::{peer_credentials, AdminList, Application, ApplicationIdentity, Authenticate};
+use crate::front::listener::ConnectionMetadata;
+use crate::utils::config::{Admin, CgroupPattern};
+use crate::utils::process;
+use anyhow::Context;
+use log::error;
+use parsec_interface::operations::list_authenticators;
+use parsec_interface::requests::request::RequestAuth;
+use parsec_interface::requests::{AuthType, ResponseStatus, Result};
+use regex::Regex;
+
+/// Control group authenticator
+#[derive(Clone, Debug)]
+pub struct CgroupAuthenticator {
+    admins: AdminList,
+    patterns: Vec<(Regex, String)>,
+}
+
+impl CgroupAuthenticator {
+    /// Create a new control group authenticator, naming the applications with the first of
+    /// `patterns` matching their control group.
+    pub fn new(admins: Vec<Admin>, patterns: &[CgroupPattern]) -> anyhow::Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                let regex = Regex::new(&pattern.pattern).with_context(|| {
+                    format!("Invalid control group pattern {}", pattern.pattern)
+                })?;
+                if is_reserved_name(&pattern.name) {
+                    anyhow::bail!(
+                        "The name {} of the control group pattern {} is reserved for the Unix \
+                        peer credentials and peer executable authenticators",
+                        pattern.name,
+                        pattern.pattern
+                    );
+                }
+                Ok((regex, pattern.name.clone()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(CgroupAuthenticator {
+            admins: admins.into(),
+            patterns,
+        })
+    }
+
+    /// Application name of a process in the given control group, if a pattern matches it.
+    fn application_name(&self, cgroup: &str) -> Option<String> {
+        self.patterns.iter().find_map(|(regex, name)| {
+            let captures = regex.captures(cgroup)?;
+            let mut app_name = String::new();
+            captures.expand(name, &mut app_name);
+            Some(app_name)
+        })
+    }
+}
+
+/// Whether the name could be given by another authenticator using Unix peer credentials: a UID,
+/// optionally followed by `:` and the path of an executable, or a group identity.
+fn is_reserved_name(name: &str) -> bool {
+    let prefix = name.split_once(':').map_or(name, |(prefix, _)| prefix);
+    prefix.parse::<u32>().is_ok() || name.starts_with("group:")
+}
+
+/// Path of the control group in the unified hierarchy, or else in the systemd hierarchy, from the
+/// content of a `/proc/<pid>/cgroup` file.
+fn cgroup_path(cgroups: &str) -> Option<&str> {
+    let paths: Vec<(&str, &str)> = cgroups
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ':');
+            let _ = fields.next()?;
+            Some((fields.next()?, fields.next()?))
+        })
+        .collect();
+    paths
+        .iter()
+        .find(|(controllers, _)| controllers.is_empty())
+        .or_else(|| {
+            paths
+                .iter()
+                .find(|(controllers, _)| *controllers == "name=systemd")
+        })
+        .map(|(_, path)| *path)
+}
+
+impl Authenticate for CgroupAuthenticator {
+    fn describe(&self) -> Result<list_authenticators::AuthenticatorInfo> {
+        Ok(list_authenticators::AuthenticatorInfo {
+            description: String::from(
+                "Uses Unix peer credentials to authenticate the client, identified by its control \
+                group, for example its systemd unit or container.",
+            ),
+            version_maj: 0,
+            version_min: 1,
+            version_rev: 0,
+            id: AuthType::UnixPeerCredentials,
+        })
+    }
+
+    fn authenticate(
+        &self,
+        auth: &RequestAuth,
+        meta: Option<ConnectionMetadata>,
+    ) -> Result<Application> {
+        let (_, _, pid, start_time) = peer_credentials::verify(auth, meta)?;
+        let (pid, start_time) = pid.zip(start_time).ok_or_else(|| {
+            error!("The PID and start time of the client process are not known.");
+            ResponseStatus::AuthenticationError
+        })?;
+
+        let cgroups = std::fs::read_to_string(format!("/proc/{}/cgroup", pid))
+            .and_then(|cgroups| process::check_start_time(pid, start_time).map(|_| cgroups))
+            .map_err(|e| {
+                format_error!("Failed to read the control group of the client", e);
+                ResponseStatus::AuthenticationError
+            })?;
+        let cgroup = cgroup_path(&cgroups).ok_or_else(|| {
+            error!("The control group of the client could not be found.");
+            ResponseStatus::AuthenticationError
+        })?;
+        let app_name = match self.application_name(cgroup) {
+            Some(app_name) if is_reserved_name(&app_name) => {
+                error!(
+                    "The control group {} of the client is named {}, which is reserved for other \
+                    authenticators.",
+                    cgroup, app_name
+                );
+                return Err(ResponseStatus::AuthenticationError);
+            }
+            Some(app_name) if !app_name.is_empty() => app_name,
+            _ => {
+                error!(
+                    "The control group {} of the client does not match any pattern.",
+                    cgroup
+                );
+                return Err(ResponseStatus::AuthenticationError);
+            }
+        };
+        let is_admin = self.admins.is_admin(&app_name);
+        Ok(Application {
+            identity: ApplicationIdentity {
+                name: app_name,
+                authenticator_id: AuthType::UnixPeerCredentials,
+            },
+            is_admin,
+        })
+    }
+
+    fn is_connection_bound(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Authenticate;
+    use super::{cgroup_path, is_reserved_name, CgroupAuthenticator};
+    use crate::front::listener::ConnectionMetadata;
+    use crate::utils::config::CgroupPattern;
+    use crate::utils::process;
+    use libc::{getgid, getpid, getuid};
+    use parsec_interface::requests::request::RequestAuth;
+    use parsec_interface::requests::ResponseStatus;
+
+    fn patterns(patterns: &str) -> Vec<CgroupPattern> {
+        #[derive(serde::Deserialize)]
+        struct Patterns {
+            patterns: Vec<CgroupPattern>,
+        }
+        toml::from_str::<Patterns>(patterns).unwrap().patterns
+    }
+
+    #[test]
+    fn cgroup_names() {
+        let authenticator = CgroupAuthenticator::new(
+            Vec::new(),
+            &patterns(
+                r#"patterns = [
+                    { pattern = '^/system\.slice/(?P<unit>[^/]+)\.service$', name = "service:$unit" },
+                    { pattern = '^/kubepods\.slice/.*/cri-containerd-(?P<id>[0-9a-f]+)\.scope$', name = "container:$id" },
+                ]"#,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(
+            authenticator.application_name("/system.slice/sshd.service"),
+            Some("service:sshd".to_string())
+        );
+        assert_eq!(
+            authenticator.application_name(
+                "/kubepods.slice/kubepods-pod1.slice/cri-containerd-0123abcd.scope"
+            ),
+            Some("container:0123abcd".to_string())
+        );
+        assert_eq!(
+            authenticator
+                .application_name("/user.slice/user-1000.slice/user@1000.service/sshd.service"),
+            None
+        );
+    }
+
+    #[test]
+    fn cgroup_hierarchies() {
+        assert_eq!(
+            cgroup_path("12:pids:/system.slice\n1:name=systemd:/system.slice/a.service\n0::/b\n"),
+            Some("/b")
+        );
+        assert_eq!(
+            cgroup_path("12:pids:/system.slice\n1:name=systemd:/system.slice/a.service\n"),
+            Some("/system.slice/a.service")
+        );
+        assert_eq!(cgroup_path("12:pids:/system.slice\n"), None);
+    }
+
+    #[test]
+    fn own_cgroup() {
+        let (uid, gid, pid) = unsafe { (getuid(), getgid(), getpid()) };
+        let req_auth = RequestAuth::new(uid.to_le_bytes().to_vec());
+        let start_time = process::start_time(pid).unwrap();
+        let metadata = |start_time| {
+            Some(ConnectionMetadata::UnixPeerCredentials {
+                uid,
+                gid,
+                pid: Some(pid),
+                start_time: Some(start_time),
+            })
+        };
+        let own_cgroup = std::fs::read_to_string(format!("/proc/{}/cgroup", pid)).unwrap();
+        let own_cgroup = cgroup_path(&own_cgroup).unwrap().to_string();
+
+        let admin = toml::from_str(&format!("name = 'cgroup:{}'", own_cgroup)).unwrap();
+        let authenticator = CgroupAuthenticator::new(
+            vec![admin],
+            &patterns(r#"patterns = [ { pattern = '^.*$', name = "cgroup:$0" } ]"#),
+        )
+        .unwrap();
+        let application = authenticator
+            .authenticate(&req_auth, metadata(start_time))
+            .expect("Failed to authenticate");
+        assert_eq!(application.identity.name, format!("cgroup:{}", own_cgroup));
+        assert!(application.is_admin);
+
+        assert_eq!(
+            authenticator
+                .authenticate(&req_auth, metadata(start_time + 1))
+                .unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+
+        for patterns in [
+            r#"patterns = [ { pattern = '^/nowhere$', name = "nowhere" } ]"#,
+            // The name is only found to be a UID once expanded.
+            r#"patterns = [ { pattern = '^(?P<none>).*$', name = "${none}1000" } ]"#,
+        ] {
+            let authenticator =
+                CgroupAuthenticator::new(Vec::new(), &self::patterns(patterns)).unwrap();
+            assert_eq!(
+                authenticator
+                    .authenticate(&req_auth, metadata(start_time))
+                    .unwrap_err(),
+                ResponseStatus::AuthenticationError
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_names() {
+        for name in ["1000", "1000:/usr/bin/daemon", "group:1000"] {
+            assert!(is_reserved_name(name));
+        }
+        for name in ["service:sshd", "container:1000", "1000-service", ""] {
+            assert!(!is_reserved_name(name));
+        }
+
+        let _ = CgroupAuthenticator::new(
+            Vec::new(),
+            &patterns(r#"patterns = [ { pattern = '^/system\.slice/.*$', name = "0" } ]"#),
+        )
+        .unwrap_err();
+    }
+}
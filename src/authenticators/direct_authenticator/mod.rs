@@ -96,7 +96,7 @@ mod test {
         let conn_metadata = None;
 
         let application = authenticator
-            .authenticate(&req_auth, conn_metadata)
+            .authenticate(&req_auth, conn_metadata.clone())
             .expect("Failed to authenticate");
 
         assert_eq!(application.identity.name, app_name);
@@ -142,7 +142,7 @@ mod test {
         let conn_metadata = None;
 
         let application = authenticator
-            .authenticate(&req_auth, conn_metadata)
+            .authenticate(&req_auth, conn_metadata.clone())
             .expect("Failed to authenticate");
 
         assert_eq!(application.identity.name, app_name);
@@ -0,0 +1,301 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! JWT authenticator
+//!
+//! The `JwtAuthenticator` validates JSON Web Tokens issued by OpenID Connect providers, for example
+//! Kubernetes projected service account tokens. The tokens are validated offline against the JSON
+//! Web Key Sets of the trusted issuers, read from files at start-up. The signature, issuer,
+//! audience, expiry and "not before" time of the tokens are checked, and the application name is
+//! taken from a configurable claim of the token.
+
+use super::{AdminList, Application, ApplicationIdentity, Authenticate};
+use crate::front::listener::ConnectionMetadata;
+use crate::utils::config::{Admin, JwtIssuerConfig};
+use anyhow::Context;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::error;
+use parsec_interface::operations::list_authenticators;
+use parsec_interface::requests::request::RequestAuth;
+use parsec_interface::requests::{AuthType, ResponseStatus, Result};
+use parsec_interface::secrecy::ExposeSecret;
+use serde_json::{Map, Value};
+use std::str;
+
+/// Claim containing the application name if none is configured
+const DEFAULT_NAME_CLAIM: &str = "sub";
+
+/// JWT authenticator
+#[allow(missing_debug_implementations)]
+pub struct JwtAuthenticator {
+    issuers: Vec<Issuer>,
+    audience: Vec<String>,
+    name_claim: String,
+    admins: AdminList,
+}
+
+/// Trusted token issuer and the keys its tokens are signed with
+struct Issuer {
+    name: String,
+    keys: Vec<IssuerKey>,
+}
+
+struct IssuerKey {
+    id: Option<String>,
+    algorithm: Option<Algorithm>,
+    key: DecodingKey,
+}
+
+impl JwtAuthenticator {
+    /// Create a new JWT authenticator trusting the tokens signed by `issuers` for one of the
+    /// `audience` values. The application name is read from the `name_claim` claim, `sub` by
+    /// default.
+    pub fn new(
+        issuers: &[JwtIssuerConfig],
+        audience: Vec<String>,
+        name_claim: Option<String>,
+        admins: Vec<Admin>,
+    ) -> anyhow::Result<Self> {
+        if audience.is_empty() {
+            anyhow::bail!("The JWT authenticator needs at least one audience");
+        }
+        let issuers = issuers
+            .iter()
+            .map(Issuer::load)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(JwtAuthenticator {
+            issuers,
+            audience,
+            name_claim: name_claim.unwrap_or_else(|| DEFAULT_NAME_CLAIM.to_string()),
+            admins: admins.into(),
+        })
+    }
+
+    /// Validate the token against the keys of the issuers, returning its claims.
+    ///
+    /// Keys are matched on the `kid` header of the token; tokens without one are tried against all
+    /// the keys. The `iss` claim must name the issuer owning the key.
+    fn validate(&self, token: &str) -> std::result::Result<Map<String, Value>, String> {
+        let header = jsonwebtoken::decode_header(token).map_err(|e| e.to_string())?;
+        let mut last_error = String::from("no key of the trusted issuers matches the token");
+        for issuer in &self.issuers {
+            for key in &issuer.keys {
+                if header.kid.is_some() && key.id != header.kid {
+                    continue;
+                }
+                if matches!(key.algorithm, Some(algorithm) if algorithm != header.alg) {
+                    continue;
+                }
+                let mut validation = Validation::new(header.alg);
+                validation.validate_nbf = true;
+                validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+                validation.set_audience(&self.audience);
+                validation.set_issuer(&[&issuer.name]);
+                match jsonwebtoken::decode::<Map<String, Value>>(token, &key.key, &validation) {
+                    Ok(token_data) => return Ok(token_data.claims),
+                    Err(e) => last_error = e.to_string(),
+                }
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl Issuer {
+    fn load(config: &JwtIssuerConfig) -> anyhow::Result<Self> {
+        let jwks = std::fs::read(&config.jwks_path)
+            .with_context(|| format!("Failed to read the JWKS file {}", config.jwks_path))?;
+        let jwks: JwkSet = serde_json::from_slice(&jwks)
+            .with_context(|| format!("Invalid JWKS document in {}", config.jwks_path))?;
+        let keys = jwks
+            .keys
+            .iter()
+            .map(|jwk| {
+                Ok(IssuerKey {
+                    id: jwk.common.key_id.clone(),
+                    algorithm: jwk.common.algorithm,
+                    key: DecodingKey::from_jwk(jwk).with_context(|| {
+                        format!("Unsupported key in the JWKS file {}", config.jwks_path)
+                    })?,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Issuer {
+            name: config.issuer.clone(),
+            keys,
+        })
+    }
+}
+
+impl Authenticate for JwtAuthenticator {
+    fn describe(&self) -> Result<list_authenticators::AuthenticatorInfo> {
+        Ok(list_authenticators::AuthenticatorInfo {
+            description: String::from(
+                "Authenticator validating JSON Web Tokens signed by trusted issuers",
+            ),
+            version_maj: 0,
+            version_min: 1,
+            version_rev: 0,
+            id: AuthType::Jwt,
+        })
+    }
+
+    fn authenticate(
+        &self,
+        auth: &RequestAuth,
+        _: Option<ConnectionMetadata>,
+    ) -> Result<Application> {
+        let token = str::from_utf8(auth.buffer.expose_secret()).map_err(|e| {
+            error!(
+                "The authentication buffer can not be parsed into a UTF-8 string ({}).",
+                e
+            );
+            ResponseStatus::InvalidEncoding
+        })?;
+
+        let claims = self.validate(token).map_err(|e| {
+            error!("The validation of the JWT failed ({}).", e);
+            ResponseStatus::AuthenticationError
+        })?;
+        let app_name = match claims.get(&self.name_claim) {
+            Some(Value::String(name)) if !name.is_empty() => name.clone(),
+            _ => {
+                error!(
+                    "The JWT does not contain the \"{}\" claim as a string.",
+                    self.name_claim
+                );
+                return Err(ResponseStatus::AuthenticationError);
+            }
+        };
+        let is_admin = self.admins.is_admin(&app_name);
+        Ok(Application {
+            identity: ApplicationIdentity {
+                name: app_name,
+                authenticator_id: AuthType::Jwt,
+            },
+            is_admin,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Authenticate;
+    use super::JwtAuthenticator;
+    use crate::utils::config::JwtIssuerConfig;
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use parsec_interface::requests::request::RequestAuth;
+    use parsec_interface::requests::ResponseStatus;
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"parsec-jwt-authenticator-test-key";
+
+    fn authenticator(name: &str) -> JwtAuthenticator {
+        let path = std::env::temp_dir().join(format!("parsec-jwt-test-{}.jwks", name));
+        let jwks = json!({ "keys": [{
+            "kty": "oct",
+            "kid": "key-1",
+            "alg": "HS256",
+            "k": base64::encode_config(SECRET, base64::URL_SAFE_NO_PAD),
+        }]});
+        std::fs::write(&path, jwks.to_string()).unwrap();
+        let issuer: JwtIssuerConfig = toml::from_str(&format!(
+            "issuer = 'https://issuer.example'\njwks_path = '{}'",
+            path.display()
+        ))
+        .unwrap();
+        let admin = toml::from_str("name = 'system:serviceaccount:ops:admin'").unwrap();
+        let authenticator =
+            JwtAuthenticator::new(&[issuer], vec!["parsec".to_string()], None, vec![admin])
+                .unwrap();
+        let _ = std::fs::remove_file(path);
+        authenticator
+    }
+
+    fn token(claims: serde_json::Value) -> RequestAuth {
+        let header = Header {
+            kid: Some("key-1".to_string()),
+            ..Default::default()
+        };
+        let token = encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap();
+        RequestAuth::new(token.into_bytes())
+    }
+
+    #[test]
+    fn valid_token() {
+        let authenticator = authenticator("valid");
+        let now = get_current_timestamp();
+        let application = authenticator
+            .authenticate(
+                &token(json!({
+                    "iss": "https://issuer.example",
+                    "aud": ["other", "parsec"],
+                    "sub": "system:serviceaccount:default:app",
+                    "exp": now + 600,
+                    "nbf": now - 10,
+                })),
+                None,
+            )
+            .expect("Failed to authenticate");
+        assert_eq!(
+            application.identity.name,
+            "system:serviceaccount:default:app"
+        );
+        assert!(!application.is_admin);
+
+        let application = authenticator
+            .authenticate(
+                &token(json!({
+                    "iss": "https://issuer.example",
+                    "aud": "parsec",
+                    "sub": "system:serviceaccount:ops:admin",
+                    "exp": now + 600,
+                })),
+                None,
+            )
+            .expect("Failed to authenticate");
+        assert!(application.is_admin);
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        let authenticator = authenticator("invalid");
+        let now = get_current_timestamp();
+        let valid = json!({
+            "iss": "https://issuer.example",
+            "aud": "parsec",
+            "sub": "app",
+            "exp": now + 600,
+        });
+        for (claim, value) in [
+            ("iss", json!("https://other.example")),
+            ("aud", json!("other")),
+            ("exp", json!(now - 600)),
+            ("nbf", json!(now + 600)),
+            ("sub", json!(42)),
+        ] {
+            let mut claims = valid.clone();
+            claims[claim] = value;
+            assert_eq!(
+                authenticator
+                    .authenticate(&token(claims), None)
+                    .expect_err("Authentication should have failed"),
+                ResponseStatus::AuthenticationError,
+                "invalid {} claim",
+                claim
+            );
+        }
+
+        let header = Header {
+            kid: Some("key-1".to_string()),
+            ..Default::default()
+        };
+        let forged = encode(&header, &valid, &EncodingKey::from_secret(b"other")).unwrap();
+        assert_eq!(
+            authenticator
+                .authenticate(&RequestAuth::new(forged.into_bytes()), None)
+                .expect_err("Authentication should have failed"),
+            ResponseStatus::AuthenticationError
+        );
+    }
+}
@@ -13,6 +13,9 @@
     feature = "direct-authenticator",
     feature = "unix-peer-credentials-authenticator",
     feature = "jwt-svid-authenticator",
+    feature = "jwt-authenticator",
+    feature = "peer-executable-authenticator",
+    feature = "cgroup-authenticator",
 )))]
 compile_error!("Please provide in at least one authenticator");
 
@@ -25,6 +28,22 @@ pub mod unix_peer_credentials_authenticator;
 #[cfg(feature = "jwt-svid-authenticator")]
 pub mod jwt_svid_authenticator;
 
+#[cfg(feature = "jwt-authenticator")]
+pub mod jwt_authenticator;
+
+#[cfg(feature = "peer-executable-authenticator")]
+pub mod peer_executable_authenticator;
+
+#[cfg(feature = "cgroup-authenticator")]
+pub mod cgroup_authenticator;
+
+#[cfg(any(
+    feature = "unix-peer-credentials-authenticator",
+    feature = "peer-executable-authenticator",
+    feature = "cgroup-authenticator",
+))]
+mod peer_credentials;
+
 use crate::front::listener::ConnectionMetadata;
 use crate::utils::config::Admin;
 use parsec_interface::operations::list_authenticators;
@@ -131,6 +150,15 @@ pub trait Authenticate {
         auth: &RequestAuth,
         meta: Option<ConnectionMetadata>,
     ) -> Result<Application>;
+
+    /// Whether the result of an authentication only depends on the authentication payload and on
+    /// the metadata of the connection it was received on.
+    ///
+    /// If so, the service can reuse the `Application` returned for the following requests sent on
+    /// the same persistent connection with the same payload, instead of authenticating them again.
+    fn is_connection_bound(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -0,0 +1,61 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Unix peer credentials checks
+//!
+//! The Unix peer credentials, peer executable and control group authenticators all identify the
+//! client from the Unix peer credentials of its connection. As no authentication type can be added
+//! to the Parsec wire protocol, they are all used for requests with the Unix peer credentials
+//! authentication type, with which clients are compatible: the authentication field of those
+//! requests is the UID of the client, which must be the one found in the peer credentials.
+use crate::front::listener::ConnectionMetadata;
+use log::error;
+use parsec_interface::requests::request::RequestAuth;
+use parsec_interface::requests::{ResponseStatus, Result};
+use parsec_interface::secrecy::ExposeSecret;
+use std::convert::TryInto;
+
+const EXPECTED_UID_SIZE_BYTES: usize = 4;
+
+/// Check that the UID declared in the request is the one found in the peer credentials, and
+/// return the UID, GID, PID and start time of the client.
+pub(super) fn verify(
+    auth: &RequestAuth,
+    meta: Option<ConnectionMetadata>,
+) -> Result<(u32, u32, Option<i32>, Option<u64>)> {
+    let expected_uid_bytes = auth.buffer.expose_secret();
+    let expected_uid: [u8; EXPECTED_UID_SIZE_BYTES] =
+        expected_uid_bytes.as_slice().try_into().map_err(|_| {
+            error!(
+                "UID in authentication request is not the right size (expected: {}, got: {}).",
+                EXPECTED_UID_SIZE_BYTES,
+                expected_uid_bytes.len()
+            );
+            ResponseStatus::AuthenticationError
+        })?;
+    let expected_uid = u32::from_le_bytes(expected_uid);
+
+    let (uid, gid, pid, start_time) = match meta {
+        Some(ConnectionMetadata::UnixPeerCredentials {
+            uid,
+            gid,
+            pid,
+            start_time,
+        }) => (uid, gid, pid, start_time),
+        Some(_) => {
+            error!("Wrong metadata type given to a Unix peer credentials authenticator.");
+            return Err(ResponseStatus::AuthenticationError);
+        }
+        None => {
+            error!("Authenticator did not receive any metadata; cannot perform authentication.");
+            return Err(ResponseStatus::AuthenticationError);
+        }
+    };
+
+    // Authentication is only successful if the _actual_ UID from the Unix peer credentials equals
+    // the self-declared UID in the authentication request.
+    if uid != expected_uid {
+        error!("Declared UID in authentication request does not match the process's UID.");
+        return Err(ResponseStatus::AuthenticationError);
+    }
+    Ok((uid, gid, pid, start_time))
+}
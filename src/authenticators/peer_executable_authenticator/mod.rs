@@ -0,0 +1,200 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Peer executable authenticator
+//!
+//! The `PeerExecutableAuthenticator` identifies the connecting process by the executable it runs,
+//! so that several daemons running as the same user do not share their keys. Like the Unix peer
+//! credentials authenticator, it checks that the UID declared in the request is the one found in
+//! the peer credentials. It then resolves the executable of the peer from its PID, through
+//! `/proc/<pid>/exe`, and optionally computes the SHA-256 digest of the executable file.
+//!
+//! The application name is `<uid>:<executable path>`, followed by `@sha256:<hex digest>` if the
+//! digest is computed. Processes of different users running the same executable are distinct
+//! applications.
+//!
+//! The PID of a client can be reused by another process once the client exits. The start time of
+//! the process, recorded when the connection was accepted, is checked again after the executable
+//! has been resolved: if it changed, the authentication fails.
+//!
+//! Reading the executable of processes owned by other users requires the service to have the
+//! `CAP_SYS_PTRACE` capability.
+//!
+//! The authenticator is used for requests with the Unix peer credentials authentication type.
+
+use super::{peer_credentials, AdminList, Application, ApplicationIdentity, Authenticate};
+use crate::front::listener::ConnectionMetadata;
+use crate::utils::config::Admin;
+use crate::utils::process;
+use log::error;
+use parsec_interface::operations::list_authenticators;
+use parsec_interface::requests::request::RequestAuth;
+use parsec_interface::requests::{AuthType, ResponseStatus, Result};
+use ring::digest::{Context, SHA256};
+use std::fs::File;
+use std::io::Read;
+
+/// Peer executable authenticator
+#[derive(Clone, Debug)]
+pub struct PeerExecutableAuthenticator {
+    admins: AdminList,
+    hash_executable: bool,
+}
+
+impl PeerExecutableAuthenticator {
+    /// Create a new peer executable authenticator. If `hash_executable` is set, the digest of the
+    /// executable is part of the application name.
+    pub fn new(admins: Vec<Admin>, hash_executable: bool) -> Self {
+        PeerExecutableAuthenticator {
+            admins: admins.into(),
+            hash_executable,
+        }
+    }
+
+    /// Application name of the process, checking that its PID was not reused since `start_time`.
+    fn application_name(&self, uid: u32, pid: i32, start_time: u64) -> std::io::Result<String> {
+        let exe = format!("/proc/{}/exe", pid);
+        let path = std::fs::read_link(&exe)?;
+        let mut app_name = format!("{}:{}", uid, path.display());
+        if self.hash_executable {
+            // The link opens the file the process was started from, even if it was since
+            // replaced or deleted.
+            app_name.push_str(&format!("@sha256:{}", sha256(File::open(&exe)?)?));
+        }
+        process::check_start_time(pid, start_time)?;
+        Ok(app_name)
+    }
+}
+
+/// Hex-encoded SHA-256 digest of the content of the file.
+fn sha256(mut file: File) -> std::io::Result<String> {
+    let mut context = Context::new(&SHA256);
+    let mut buffer = [0; 8192];
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        context.update(&buffer[..len]);
+    }
+    Ok(hex::encode(context.finish()))
+}
+
+impl Authenticate for PeerExecutableAuthenticator {
+    fn describe(&self) -> Result<list_authenticators::AuthenticatorInfo> {
+        Ok(list_authenticators::AuthenticatorInfo {
+            description: String::from(
+                "Uses Unix peer credentials to authenticate the client, identified by its Unix \
+                user identifier (UID) and the executable it runs.",
+            ),
+            version_maj: 0,
+            version_min: 1,
+            version_rev: 0,
+            id: AuthType::UnixPeerCredentials,
+        })
+    }
+
+    fn authenticate(
+        &self,
+        auth: &RequestAuth,
+        meta: Option<ConnectionMetadata>,
+    ) -> Result<Application> {
+        let (uid, _, pid, start_time) = peer_credentials::verify(auth, meta)?;
+        let (pid, start_time) = pid.zip(start_time).ok_or_else(|| {
+            error!("The PID and start time of the client process are not known.");
+            ResponseStatus::AuthenticationError
+        })?;
+
+        let app_name = self.application_name(uid, pid, start_time).map_err(|e| {
+            format_error!("Failed to identify the executable of the client", e);
+            ResponseStatus::AuthenticationError
+        })?;
+        let is_admin = self.admins.is_admin(&app_name);
+        Ok(Application {
+            identity: ApplicationIdentity {
+                name: app_name,
+                authenticator_id: AuthType::UnixPeerCredentials,
+            },
+            is_admin,
+        })
+    }
+
+    fn is_connection_bound(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::Authenticate;
+    use super::{sha256, PeerExecutableAuthenticator};
+    use crate::front::listener::ConnectionMetadata;
+    use crate::utils::process;
+    use libc::{getgid, getpid, getuid};
+    use parsec_interface::requests::request::RequestAuth;
+    use parsec_interface::requests::ResponseStatus;
+    use std::fs::File;
+
+    fn metadata(start_time: Option<u64>) -> Option<ConnectionMetadata> {
+        let (uid, gid, pid) = unsafe { (getuid(), getgid(), getpid()) };
+        Some(ConnectionMetadata::UnixPeerCredentials {
+            uid,
+            gid,
+            pid: Some(pid),
+            start_time,
+        })
+    }
+
+    #[test]
+    fn executable_identity() {
+        let uid = unsafe { getuid() };
+        let req_auth = RequestAuth::new(uid.to_le_bytes().to_vec());
+        let start_time = process::start_time(unsafe { getpid() }).unwrap();
+        let exe = std::env::current_exe().unwrap();
+
+        let application = PeerExecutableAuthenticator::new(Vec::new(), false)
+            .authenticate(&req_auth, metadata(Some(start_time)))
+            .expect("Failed to authenticate");
+        assert_eq!(
+            application.identity.name,
+            format!("{}:{}", uid, exe.display())
+        );
+        assert!(!application.is_admin);
+
+        let app_name = format!(
+            "{}:{}@sha256:{}",
+            uid,
+            exe.display(),
+            sha256(File::open(&exe).unwrap()).unwrap()
+        );
+        let admin = toml::from_str(&format!("name = '{}'", app_name)).unwrap();
+        let application = PeerExecutableAuthenticator::new(vec![admin], true)
+            .authenticate(&req_auth, metadata(Some(start_time)))
+            .expect("Failed to authenticate");
+        assert_eq!(application.identity.name, app_name);
+        assert!(application.is_admin);
+    }
+
+    #[test]
+    fn reused_pid_or_missing_metadata() {
+        let uid = unsafe { getuid() };
+        let req_auth = RequestAuth::new(uid.to_le_bytes().to_vec());
+        let start_time = process::start_time(unsafe { getpid() }).unwrap();
+        let authenticator = PeerExecutableAuthenticator::new(Vec::new(), false);
+
+        for meta in [metadata(Some(start_time + 1)), metadata(None), None] {
+            assert_eq!(
+                authenticator.authenticate(&req_auth, meta).unwrap_err(),
+                ResponseStatus::AuthenticationError
+            );
+        }
+        assert_eq!(
+            authenticator
+                .authenticate(
+                    &RequestAuth::new(uid.wrapping_add(1).to_le_bytes().to_vec()),
+                    metadata(Some(start_time))
+                )
+                .unwrap_err(),
+            ResponseStatus::AuthenticationError
+        );
+    }
+}
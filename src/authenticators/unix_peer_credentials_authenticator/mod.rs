@@ -4,26 +4,34 @@
 //!
 //! The `UnixPeerCredentialsAuthenticator` uses Unix peer credentials to perform authentication. As
 //! such, it uses the effective Unix user ID (UID) to authenticate the connecting process. Unix
-//! peer credentials also allow us to access the effective Unix group ID (GID) of the connecting
-//! process, although this information is currently unused.
+//! peer credentials also give the effective Unix group ID (GID) of the connecting process, and its
+//! supplementary groups are read from `/proc/<pid>/status`.
 //!
-//! Currently, the stringified UID is used as the application name.
+//! By default, the stringified UID is used as the application name. Members of the groups
+//! configured as group identities are instead named after the first of those groups they belong
+//! to, as `group:<gid>`, so that they share the same keys. Members of the admin groups are admins.
+//!
+//! If group identities or admin groups are configured, the authentication fails when the groups of
+//! the process can not be read, or when its PID was reused by another process, which is detected
+//! with the start time of the process.
 
-use super::{AdminList, Application, ApplicationIdentity, Authenticate};
+use super::{peer_credentials, AdminList, Application, ApplicationIdentity, Authenticate};
 use crate::front::listener::ConnectionMetadata;
 use crate::utils::config::Admin;
-use log::error;
+use crate::utils::process;
 use parsec_interface::operations::list_authenticators;
 use parsec_interface::requests::request::RequestAuth;
 use parsec_interface::requests::AuthType;
 use parsec_interface::requests::{ResponseStatus, Result};
-use parsec_interface::secrecy::ExposeSecret;
-use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+use std::iter;
 
 /// Unix peer credentials authenticator.
 #[derive(Clone, Debug)]
 pub struct UnixPeerCredentialsAuthenticator {
     admins: AdminList,
+    group_identities: Vec<u32>,
+    admin_groups: Vec<u32>,
 }
 
 impl UnixPeerCredentialsAuthenticator {
@@ -31,8 +39,50 @@ impl UnixPeerCredentialsAuthenticator {
     pub fn new(admins: Vec<Admin>) -> Self {
         UnixPeerCredentialsAuthenticator {
             admins: admins.into(),
+            group_identities: Vec::new(),
+            admin_groups: Vec::new(),
         }
     }
+
+    /// Name the members of the given groups after the first of them they belong to.
+    pub fn with_group_identities(mut self, group_identities: Vec<u32>) -> Self {
+        self.group_identities = group_identities;
+        self
+    }
+
+    /// Give admin rights to the members of the given groups.
+    pub fn with_admin_groups(mut self, admin_groups: Vec<u32>) -> Self {
+        self.admin_groups = admin_groups;
+        self
+    }
+
+    /// Effective and supplementary groups of the peer.
+    ///
+    /// The supplementary groups can only be found if the PID and start time of the peer are known.
+    fn groups(gid: u32, pid: Option<i32>, start_time: Option<u64>) -> std::io::Result<Vec<u32>> {
+        let (pid, start_time) = pid.zip(start_time).ok_or_else(|| {
+            Error::new(
+                ErrorKind::NotFound,
+                "the PID and start time of the process are not known",
+            )
+        })?;
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid))?;
+        process::check_start_time(pid, start_time)?;
+        let supplementary = supplementary_groups(&status).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "invalid /proc/<pid>/status format")
+        })?;
+        Ok(iter::once(gid).chain(supplementary).collect())
+    }
+}
+
+/// Parse the `Groups` line of the content of a `/proc/<pid>/status` file.
+fn supplementary_groups(status: &str) -> Option<Vec<u32>> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Groups:"))?
+        .split_whitespace()
+        .map(|gid| gid.parse().ok())
+        .collect()
 }
 
 impl Authenticate for UnixPeerCredentialsAuthenticator {
@@ -55,61 +105,47 @@ impl Authenticate for UnixPeerCredentialsAuthenticator {
         auth: &RequestAuth,
         meta: Option<ConnectionMetadata>,
     ) -> Result<Application> {
-        // Parse authentication request.
-        let expected_uid_bytes = auth.buffer.expose_secret();
-
-        const EXPECTED_UID_SIZE_BYTES: usize = 4;
-        let expected_uid: [u8; EXPECTED_UID_SIZE_BYTES] =
-            expected_uid_bytes.as_slice().try_into().map_err(|_| {
-                error!(
-                    "UID in authentication request is not the right size (expected: {}, got: {}).",
-                    EXPECTED_UID_SIZE_BYTES,
-                    expected_uid_bytes.len()
-                );
+        let (uid, gid, pid, start_time) = peer_credentials::verify(auth, meta)?;
+        let groups = if self.group_identities.is_empty() && self.admin_groups.is_empty() {
+            Vec::new()
+        } else {
+            Self::groups(gid, pid, start_time).map_err(|e| {
+                format_error!("Failed to find the groups of the client", e);
                 ResponseStatus::AuthenticationError
-            })?;
-        let expected_uid = u32::from_le_bytes(expected_uid);
-
-        let meta = meta.ok_or_else(|| {
-            error!("Authenticator did not receive any metadata; cannot perform authentication.");
-            ResponseStatus::AuthenticationError
-        })?;
-
-        #[allow(unreachable_patterns)]
-        let (uid, _gid, _pid) = match meta {
-            ConnectionMetadata::UnixPeerCredentials { uid, gid, pid } => (uid, gid, pid),
-            _ => {
-                error!("Wrong metadata type given to Unix peer credentials authenticator.");
-                return Err(ResponseStatus::AuthenticationError);
-            }
+            })?
+        };
+        let app_name = match self
+            .group_identities
+            .iter()
+            .find(|gid| groups.contains(gid))
+        {
+            Some(gid) => format!("group:{}", gid),
+            None => uid.to_string(),
         };
+        let is_admin = self.admins.is_admin(&app_name)
+            || self.admin_groups.iter().any(|gid| groups.contains(gid));
+        Ok(Application {
+            identity: ApplicationIdentity {
+                name: app_name,
+                authenticator_id: AuthType::UnixPeerCredentials,
+            },
+            is_admin,
+        })
+    }
 
-        // Authentication is successful if the _actual_ UID from the Unix peer credentials equals
-        // the self-declared UID in the authentication request.
-        if uid == expected_uid {
-            let app_name = uid.to_string();
-            let is_admin = self.admins.is_admin(&app_name);
-            Ok(Application {
-                identity: ApplicationIdentity {
-                    name: app_name,
-                    authenticator_id: AuthType::UnixPeerCredentials,
-                },
-                is_admin,
-            })
-        } else {
-            error!("Declared UID in authentication request does not match the process's UID.");
-            Err(ResponseStatus::AuthenticationError)
-        }
+    fn is_connection_bound(&self) -> bool {
+        true
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::super::Authenticate;
-    use super::UnixPeerCredentialsAuthenticator;
+    use super::{supplementary_groups, UnixPeerCredentialsAuthenticator};
     use crate::front::domain_socket::peer_credentials;
     use crate::front::listener::ConnectionMetadata;
-    use libc::{getuid, uid_t};
+    use crate::utils::process;
+    use libc::{getgid, getpid, getuid, uid_t};
     use parsec_interface::requests::request::RequestAuth;
     use parsec_interface::requests::ResponseStatus;
     use rand::Rng;
@@ -127,9 +163,7 @@ mod test {
             peer_credentials::peer_cred(&_sock_b).unwrap(),
         );
 
-        let authenticator = UnixPeerCredentialsAuthenticator {
-            admins: Default::default(),
-        };
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new());
 
         let req_auth_data = cred_a.uid.to_le_bytes().to_vec();
         let req_auth = RequestAuth::new(req_auth_data);
@@ -137,6 +171,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: None,
+            start_time: None,
         });
 
         let application = authenticator
@@ -160,9 +195,7 @@ mod test {
             peer_credentials::peer_cred(&_sock_b).unwrap(),
         );
 
-        let authenticator = UnixPeerCredentialsAuthenticator {
-            admins: Default::default(),
-        };
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new());
 
         let wrong_uid = cred_a.uid + 1;
         let wrong_req_auth_data = wrong_uid.to_le_bytes().to_vec();
@@ -171,6 +204,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: cred_a.pid,
+            start_time: None,
         });
 
         let auth_result = authenticator
@@ -190,9 +224,7 @@ mod test {
             peer_credentials::peer_cred(&_sock_b).unwrap(),
         );
 
-        let authenticator = UnixPeerCredentialsAuthenticator {
-            admins: Default::default(),
-        };
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new());
 
         let garbage_data = rand::thread_rng().gen::<[u8; 32]>().to_vec();
         let req_auth = RequestAuth::new(garbage_data);
@@ -200,6 +232,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: cred_a.pid,
+            start_time: None,
         });
 
         let auth_result = authenticator
@@ -210,9 +243,7 @@ mod test {
 
     #[test]
     fn unsuccessful_authentication_no_metadata() {
-        let authenticator = UnixPeerCredentialsAuthenticator {
-            admins: Default::default(),
-        };
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new());
         let req_auth = RequestAuth::new("secret".into());
 
         let conn_metadata = None;
@@ -233,9 +264,7 @@ mod test {
 
         let current_uid: uid_t = unsafe { getuid() };
         let admin = toml::from_str(&format!("name = '{}'", current_uid)).unwrap();
-        let authenticator = UnixPeerCredentialsAuthenticator {
-            admins: vec![admin].into(),
-        };
+        let authenticator = UnixPeerCredentialsAuthenticator::new(vec![admin]);
 
         let req_auth_data = cred_a.uid.to_le_bytes().to_vec();
         let req_auth = RequestAuth::new(req_auth_data);
@@ -243,6 +272,7 @@ mod test {
             uid: cred_a.uid,
             gid: cred_a.gid,
             pid: None,
+            start_time: None,
         });
 
         let application = authenticator
@@ -253,10 +283,71 @@ mod test {
         assert!(application.is_admin);
     }
 
+    #[test]
+    fn group_identity_and_admin_group() {
+        let (uid, gid, pid) = unsafe { (getuid(), getgid(), getpid()) };
+        let req_auth = RequestAuth::new(uid.to_le_bytes().to_vec());
+        let start_time = process::start_time(pid).unwrap();
+        let metadata = |start_time| {
+            Some(ConnectionMetadata::UnixPeerCredentials {
+                uid,
+                gid,
+                pid: Some(pid),
+                start_time,
+            })
+        };
+        let conn_metadata = metadata(Some(start_time));
+
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new())
+            .with_group_identities(vec![gid.wrapping_add(1), gid])
+            .with_admin_groups(vec![gid]);
+        let application = authenticator
+            .authenticate(&req_auth, conn_metadata.clone())
+            .expect("Failed to authenticate");
+        assert_eq!(application.identity.name, format!("group:{}", gid));
+        assert!(application.is_admin);
+
+        // The groups of a process whose PID was reused, or is not known, can not be found.
+        for meta in [metadata(Some(start_time + 1)), metadata(None)] {
+            assert_eq!(
+                authenticator.authenticate(&req_auth, meta).unwrap_err(),
+                ResponseStatus::AuthenticationError
+            );
+        }
+
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new())
+            .with_group_identities(vec![gid.wrapping_add(1)])
+            .with_admin_groups(vec![gid.wrapping_add(1)]);
+        let application = authenticator
+            .authenticate(&req_auth, conn_metadata)
+            .expect("Failed to authenticate");
+        assert_eq!(application.identity.name, uid.to_string());
+        assert!(!application.is_admin);
+    }
+
+    #[test]
+    fn parse_supplementary_groups() {
+        let status = "Name:\tparsec\nUid:\t1000\t1000\t1000\t1000\n\
+                      Gid:\t1000\t1000\t1000\t1000\nGroups:\t4 27 1000 \nNgid:\t0\n";
+        assert_eq!(supplementary_groups(status), Some(vec![4, 27, 1000]));
+        assert_eq!(supplementary_groups("Groups:\t\n"), Some(Vec::new()));
+        assert_eq!(supplementary_groups("Groups:\tfoo\n"), None);
+        assert_eq!(supplementary_groups("Name:\tparsec\n"), None);
+    }
+
     #[test]
     fn unsuccessful_authentication_wrong_metadata() {
-        // TODO(new_metadata_variant): this test needs implementing when we have more than one
-        // metadata type. At the moment, the compiler just complains with an 'unreachable branch'
-        // message.
+        let authenticator = UnixPeerCredentialsAuthenticator::new(Vec::new());
+
+        let current_uid: uid_t = unsafe { getuid() };
+        let req_auth = RequestAuth::new(current_uid.to_le_bytes().to_vec());
+        let conn_metadata = Some(ConnectionMetadata::TlsClientCertificate {
+            identity: current_uid.to_string(),
+        });
+
+        let auth_result = authenticator
+            .authenticate(&req_auth, conn_metadata)
+            .unwrap_err();
+        assert_eq!(auth_result, ResponseStatus::AuthenticationError);
     }
 }
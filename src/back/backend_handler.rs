@@ -7,16 +7,27 @@
 //! native operation which is then passed to the provider.
 use crate::authenticators::Application;
 use crate::providers::Provide;
+use crate::utils::config::DeadlinesConfig;
+use crate::utils::correlation_id::{CorrelationId, CorrelationScope};
 use derivative::Derivative;
-use log::{error, trace, warn};
+use log::{error, info, trace, warn};
+use parsec_interface::operations::list_keys;
 use parsec_interface::operations::Convert;
 use parsec_interface::operations::{NativeOperation, NativeResult};
 use parsec_interface::requests::{
     request::RequestHeader, Request, Response, ResponseStatus, Result,
 };
-use parsec_interface::requests::{BodyType, ProviderId};
+use parsec_interface::requests::{BodyType, Opcode, ProviderId};
+use std::collections::HashMap;
 use std::io::{Error, ErrorKind};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Default number of operations of a provider which can run past their deadline at the same time
+const DEFAULT_MAX_OVERDUE_OPERATIONS: usize = 4;
 
 /// Back end handler component
 ///
@@ -25,24 +36,48 @@ use std::sync::Arc;
 ///
 /// It also provides assessment capabilities, letting the dispatcher know if
 /// it can process a request.
-#[derive(Derivative)]
+///
+/// Operations which have a deadline are executed on a thread of their own. If they do not
+/// complete in time, the client gets an error and the provider is marked as degraded until one
+/// of its operations completes in time again. Operations which missed their deadline keep their
+/// thread until they complete: once too many of them are still running, the operations of the
+/// provider fail straight away instead of starting new threads.
+#[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct BackEndHandler {
     // Send and Sync are required for Arc<FrontEndHandler> to be Send.
     #[derivative(Debug = "ignore")]
     provider: Arc<dyn Provide + Send + Sync>,
     #[derivative(Debug = "ignore")]
-    converter: Box<dyn Convert + Send + Sync>,
+    converters: Vec<Arc<dyn Convert + Send + Sync>>,
     provider_id: ProviderId,
-    content_type: BodyType,
-    accept_type: BodyType,
+    content_types: Vec<BodyType>,
+    accept_types: Vec<BodyType>,
+    default_deadline: Option<Duration>,
+    deadlines: Arc<HashMap<Opcode, Duration>>,
+    max_overdue_operations: usize,
+    overdue_operations: Arc<AtomicUsize>,
+    degraded: Arc<AtomicBool>,
 }
 
 impl BackEndHandler {
+    /// Find the converter for the given body type.
+    fn converter(&self, body_type: BodyType) -> Option<&Arc<dyn Convert + Send + Sync>> {
+        self.converters
+            .iter()
+            .find(|converter| converter.body_type() == body_type)
+    }
+
     /// Convert a request into a response, given the result of the operation.
+    ///
+    /// The result is converted to the accept type of the request.
     fn result_to_response(&self, result: NativeResult, request_hdr: RequestHeader) -> Response {
         let mut response = Response::from_request_header(request_hdr, ResponseStatus::Success);
-        match self.converter.result_to_body(result) {
+        let body = match self.converter(request_hdr.accept_type) {
+            Some(converter) => converter.result_to_body(result),
+            None => Err(ResponseStatus::AcceptTypeNotSupported),
+        };
+        match body {
             Ok(body) => response.body = body,
             Err(status) => response.header.status = status,
         };
@@ -56,8 +91,8 @@ impl BackEndHandler {
     /// - if the provider ID can not perform the type of operation, returns
     /// `ResponseStatus::PsaErrorNotSupported`
     /// - if the provider ID does not match, returns `ResponseStatus::WrongProviderId`
-    /// - if the content type does not match, returns `ResponseStatus::ContentTypeNotSupported`
-    /// - if the accept type does not match, returns `ResponseStatus::AcceptTypeNotSupported`
+    /// - if the content type is not supported, returns `ResponseStatus::ContentTypeNotSupported`
+    /// - if the accept type is not supported, returns `ResponseStatus::AcceptTypeNotSupported`
     pub fn is_capable(&self, request: &Request) -> Result<()> {
         let header = &request.header;
 
@@ -68,21 +103,173 @@ impl BackEndHandler {
 
         if header.provider != self.provider_id {
             Err(ResponseStatus::WrongProviderId)
-        } else if header.content_type != self.content_type {
+        } else if !self.content_types.contains(&header.content_type) {
             Err(ResponseStatus::ContentTypeNotSupported)
-        } else if header.accept_type != self.accept_type {
+        } else if !self.accept_types.contains(&header.accept_type) {
             Err(ResponseStatus::AcceptTypeNotSupported)
         } else {
             Ok(())
         }
     }
 
+    /// ID of the provider.
+    pub fn provider_id(&self) -> ProviderId {
+        self.provider_id
+    }
+
+    /// Check whether the provider supports the operation.
+    pub fn supports_opcode(&self, opcode: Opcode) -> bool {
+        matches!(self.provider.describe(), Ok((_, opcodes)) if opcodes.contains(&opcode))
+    }
+
+    /// Check whether the provider holds a key of the application with the given name.
+    pub fn has_key(&self, app: &Application, key_name: &str) -> bool {
+        matches!(
+            self.provider.list_keys(app.identity(), list_keys::Operation {}),
+            Ok(result) if result.keys.iter().any(|key| key.name == key_name)
+        )
+    }
+
+    /// Name of the key the request operates on, if any.
+    ///
+    /// The body of the request is decoded to find it: the request is given back with its body
+    /// encoded again.
+    pub fn key_name(&self, request: Request) -> Result<(Request, Option<String>)> {
+        let Request { header, body, auth } = request;
+        let converter = self
+            .converter(header.content_type)
+            .ok_or(ResponseStatus::ContentTypeNotSupported)?;
+        let operation = converter.body_to_operation(body, header.opcode)?;
+        let key_name = match &operation {
+            NativeOperation::PsaGenerateKey(op) => Some(&op.key_name),
+            NativeOperation::PsaImportKey(op) => Some(&op.key_name),
+            NativeOperation::PsaExportPublicKey(op) => Some(&op.key_name),
+            NativeOperation::PsaExportKey(op) => Some(&op.key_name),
+            NativeOperation::PsaDestroyKey(op) => Some(&op.key_name),
+            NativeOperation::PsaSignHash(op) => Some(&op.key_name),
+            NativeOperation::PsaVerifyHash(op) => Some(&op.key_name),
+            NativeOperation::PsaSignMessage(op) => Some(&op.key_name),
+            NativeOperation::PsaVerifyMessage(op) => Some(&op.key_name),
+            NativeOperation::PsaAsymmetricEncrypt(op) => Some(&op.key_name),
+            NativeOperation::PsaAsymmetricDecrypt(op) => Some(&op.key_name),
+            NativeOperation::PsaAeadEncrypt(op) => Some(&op.key_name),
+            NativeOperation::PsaAeadDecrypt(op) => Some(&op.key_name),
+            NativeOperation::PsaCipherEncrypt(op) => Some(&op.key_name),
+            NativeOperation::PsaCipherDecrypt(op) => Some(&op.key_name),
+            NativeOperation::PsaRawKeyAgreement(op) => Some(&op.private_key_name),
+            _ => None,
+        }
+        .cloned();
+        let body = converter.operation_to_body(operation)?;
+
+        Ok((Request { header, body, auth }, key_name))
+    }
+
+    /// Check whether an operation of the provider recently missed its deadline, or whether the
+    /// provider failed.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed) || !self.provider.is_healthy()
+    }
+
     /// Unmarshall the request body, pass the operation to the provider and marshall
     /// the result back.
     ///
     /// If any of the steps fails, a response containing an appropriate status code is
-    /// returned.
+    /// returned. If the operation has a deadline and does not complete before it,
+    /// `ResponseStatus::PsaErrorCommunicationFailure` is returned; the operation itself
+    /// carries on in the background and its result is discarded. The same status is returned
+    /// without executing the operation while too many operations of the provider are overdue.
     pub fn execute_request(&self, request: Request, app: Option<Application>) -> Response {
+        let opcode = request.header.opcode;
+        let deadline = match self
+            .deadlines
+            .get(&opcode)
+            .or(self.default_deadline.as_ref())
+        {
+            Some(deadline) => *deadline,
+            None => return self.execute_request_now(request, app),
+        };
+        let header = request.header;
+
+        if self.overdue_operations.load(Ordering::Relaxed) >= self.max_overdue_operations {
+            error!(
+                "Too many operations of {} are past their deadline, {:?} is not executed.",
+                self.provider_id, opcode
+            );
+            return Response::from_request_header(
+                header,
+                ResponseStatus::PsaErrorCommunicationFailure,
+            );
+        }
+
+        // Set when the deadline passes, under the lock, so that exactly one of the caller and the
+        // executing thread accounts for the overdue operation.
+        let abandoned = Arc::new(Mutex::new(false));
+        let (sender, receiver) = mpsc::channel();
+        let backend_handler = self.clone();
+        let thread_abandoned = abandoned.clone();
+        let correlation_id = CorrelationId::current();
+        let spawned = thread::Builder::new()
+            .name(format!("{:?}", opcode))
+            .spawn(move || {
+                let _correlation_scope = correlation_id.map(CorrelationScope::enter);
+                let response = backend_handler.execute_request_now(request, app);
+                if *thread_abandoned.lock().expect("Deadline lock poisoned") {
+                    let _ = backend_handler
+                        .overdue_operations
+                        .fetch_sub(1, Ordering::Relaxed);
+                } else {
+                    let _ = sender.send(response);
+                }
+            });
+        if let Err(err) = spawned {
+            format_error!("Failed to spawn a thread to execute the operation", err);
+            return Response::from_request_header(
+                header,
+                ResponseStatus::PsaErrorInsufficientMemory,
+            );
+        }
+
+        let result = receiver.recv_timeout(deadline).or_else(|err| {
+            let mut abandoned = abandoned.lock().expect("Deadline lock poisoned");
+            // The operation could have completed just before the lock was taken.
+            receiver.try_recv().map_err(|_| {
+                if err == RecvTimeoutError::Timeout {
+                    *abandoned = true;
+                    let _ = self.overdue_operations.fetch_add(1, Ordering::Relaxed);
+                }
+                err
+            })
+        });
+        match result {
+            Ok(response) => {
+                if self.degraded.swap(false, Ordering::Relaxed) {
+                    info!(
+                        "{} completed an operation within its deadline, it is no longer degraded.",
+                        self.provider_id
+                    );
+                }
+                response
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                error!(
+                    "{:?} did not complete within its deadline of {}ms.",
+                    opcode,
+                    deadline.as_millis()
+                );
+                if !self.degraded.swap(true, Ordering::Relaxed) {
+                    warn!("{} is now marked as degraded.", self.provider_id);
+                }
+                Response::from_request_header(header, ResponseStatus::PsaErrorCommunicationFailure)
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("The thread executing {:?} panicked.", opcode);
+                Response::from_request_header(header, ResponseStatus::PsaErrorGenericError)
+            }
+        }
+    }
+
+    fn execute_request_now(&self, request: Request, app: Option<Application>) -> Response {
         trace!("execute_request ingress");
         let opcode = request.header.opcode;
         let header = request.header;
@@ -109,7 +296,10 @@ impl BackEndHandler {
             }
         }
 
-        match unwrap_or_else_return!(self.converter.body_to_operation(request.body, opcode)) {
+        let converter = unwrap_or_else_return!(self
+            .converter(header.content_type)
+            .ok_or(ResponseStatus::ContentTypeNotSupported));
+        match unwrap_or_else_return!(converter.body_to_operation(request.body, opcode)) {
             NativeOperation::ListProviders(op_list_providers) => {
                 let result =
                     unwrap_or_else_return!(self.provider.list_providers(op_list_providers));
@@ -333,10 +523,13 @@ pub struct BackEndHandlerBuilder {
     #[derivative(Debug = "ignore")]
     provider: Option<Arc<dyn Provide + Send + Sync>>,
     #[derivative(Debug = "ignore")]
-    converter: Option<Box<dyn Convert + Send + Sync>>,
+    converters: Vec<Arc<dyn Convert + Send + Sync>>,
     provider_id: Option<ProviderId>,
-    content_type: Option<BodyType>,
-    accept_type: Option<BodyType>,
+    content_types: Vec<BodyType>,
+    accept_types: Vec<BodyType>,
+    default_deadline: Option<Duration>,
+    deadlines: HashMap<Opcode, Duration>,
+    max_overdue_operations: Option<usize>,
 }
 
 impl BackEndHandlerBuilder {
@@ -344,10 +537,13 @@ impl BackEndHandlerBuilder {
     pub fn new() -> BackEndHandlerBuilder {
         BackEndHandlerBuilder {
             provider: None,
-            converter: None,
+            converters: Vec::new(),
             provider_id: None,
-            content_type: None,
-            accept_type: None,
+            content_types: Vec::new(),
+            accept_types: Vec::new(),
+            default_deadline: None,
+            deadlines: HashMap::new(),
+            max_overdue_operations: None,
         }
     }
 
@@ -358,8 +554,11 @@ impl BackEndHandlerBuilder {
     }
 
     /// Add a converter to the builder
+    ///
+    /// Several converters can be added, one per body type. The converters used for a request
+    /// are chosen based on its content and accept types.
     pub fn with_converter(mut self, converter: Box<dyn Convert + Send + Sync>) -> Self {
-        self.converter = Some(converter);
+        self.converters.push(Arc::from(converter));
         self
     }
 
@@ -369,36 +568,194 @@ impl BackEndHandlerBuilder {
         self
     }
 
-    /// Set the content type that the BackEndHandler supports
+    /// Add a content type that the BackEndHandler supports
     pub fn with_content_type(mut self, content_type: BodyType) -> Self {
-        self.content_type = Some(content_type);
+        self.content_types.push(content_type);
         self
     }
 
-    /// Set the accept type that the BackEndHandler supports
+    /// Add an accept type that the BackEndHandler supports
     pub fn with_accept_type(mut self, accept_type: BodyType) -> Self {
-        self.accept_type = Some(accept_type);
+        self.accept_types.push(accept_type);
+        self
+    }
+
+    /// Set the execution deadlines of the operations
+    pub fn with_deadlines(mut self, deadlines: &DeadlinesConfig) -> Self {
+        self.default_deadline = deadlines.default.map(Duration::from_millis);
+        self.deadlines = deadlines
+            .operations
+            .0
+            .iter()
+            .map(|(opcode, deadline)| (*opcode, Duration::from_millis(*deadline)))
+            .collect();
+        self.max_overdue_operations = deadlines.max_overdue_operations;
         self
     }
 
     /// Build into a BackEndHandler
     pub fn build(self) -> std::io::Result<BackEndHandler> {
+        if self.converters.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "converter is missing"));
+        }
+        if self.content_types.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "content_type is missing",
+            ));
+        }
+        if self.accept_types.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "accept_type is missing"));
+        }
+        for body_type in self.content_types.iter().chain(self.accept_types.iter()) {
+            if !self
+                .converters
+                .iter()
+                .any(|converter| converter.body_type() == *body_type)
+            {
+                error!("No converter was added for the {:?} body type.", body_type);
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "converter is missing for a body type",
+                ));
+            }
+        }
+
         Ok(BackEndHandler {
             provider: self
                 .provider
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "provider is missing"))?,
-            converter: self
-                .converter
-                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "converter is missing"))?,
+            converters: self.converters,
             provider_id: self
                 .provider_id
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "provider_id is missing"))?,
-            content_type: self
-                .content_type
-                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "content_type is missing"))?,
-            accept_type: self
-                .accept_type
-                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "accept_type is missing"))?,
+            content_types: self.content_types,
+            accept_types: self.accept_types,
+            default_deadline: self.default_deadline,
+            deadlines: Arc::new(self.deadlines),
+            max_overdue_operations: self
+                .max_overdue_operations
+                .unwrap_or(DEFAULT_MAX_OVERDUE_OPERATIONS),
+            overdue_operations: Arc::new(AtomicUsize::new(0)),
+            degraded: Arc::new(AtomicBool::new(false)),
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::BackEndHandlerBuilder;
+    use crate::providers::mock::MockProvider;
+    use crate::utils::config::{DeadlinesConfig, PerOpcode};
+    use parsec_interface::operations::{ping, Convert, NativeOperation};
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+    use parsec_interface::requests::{
+        AuthType, BodyType, Opcode, ProviderId, Request, ResponseStatus,
+    };
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Provider answering pings after `delay` milliseconds
+    fn slow_provider(delay: &Arc<AtomicU64>) -> Arc<MockProvider> {
+        let delay = delay.clone();
+        Arc::new(MockProvider::new(ProviderId::Core).with_ping(move |_| {
+            thread::sleep(Duration::from_millis(delay.load(Ordering::Relaxed)));
+            Ok(ping::Result {
+                wire_protocol_version_maj: 1,
+                wire_protocol_version_min: 0,
+            })
+        }))
+    }
+
+    fn ping_request() -> Request {
+        Request {
+            header: RequestHeader {
+                provider: ProviderId::Core,
+                session: 0,
+                content_type: BodyType::Protobuf,
+                accept_type: BodyType::Protobuf,
+                auth_type: AuthType::NoAuth,
+                opcode: Opcode::Ping,
+            },
+            body: ProtobufConverter {}
+                .operation_to_body(NativeOperation::Ping(ping::Operation {}))
+                .unwrap(),
+            auth: RequestAuth::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn operation_past_its_deadline() {
+        let delay = Arc::new(AtomicU64::new(500));
+        let mut deadlines = HashMap::new();
+        let _ = deadlines.insert(Opcode::Ping, 50);
+        let backend_handler = BackEndHandlerBuilder::new()
+            .with_provider(slow_provider(&delay))
+            .with_converter(Box::from(ProtobufConverter {}))
+            .with_provider_id(ProviderId::Core)
+            .with_content_type(BodyType::Protobuf)
+            .with_accept_type(BodyType::Protobuf)
+            .with_deadlines(&DeadlinesConfig {
+                default: None,
+                operations: PerOpcode(deadlines),
+                max_overdue_operations: None,
+            })
+            .build()
+            .unwrap();
+
+        let response = backend_handler.execute_request(ping_request(), None);
+        assert_eq!(
+            response.header.status,
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+        assert!(backend_handler.is_degraded());
+
+        delay.store(0, Ordering::Relaxed);
+        let response = backend_handler.execute_request(ping_request(), None);
+        assert_eq!(response.header.status, ResponseStatus::Success);
+        assert!(!backend_handler.is_degraded());
+    }
+
+    #[test]
+    fn overdue_operations_are_bounded() {
+        let delay = Arc::new(AtomicU64::new(300));
+        let backend_handler = BackEndHandlerBuilder::new()
+            .with_provider(slow_provider(&delay))
+            .with_converter(Box::from(ProtobufConverter {}))
+            .with_provider_id(ProviderId::Core)
+            .with_content_type(BodyType::Protobuf)
+            .with_accept_type(BodyType::Protobuf)
+            .with_deadlines(&DeadlinesConfig {
+                default: Some(50),
+                operations: PerOpcode(HashMap::new()),
+                max_overdue_operations: Some(1),
+            })
+            .build()
+            .unwrap();
+
+        let response = backend_handler.execute_request(ping_request(), None);
+        assert_eq!(
+            response.header.status,
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+
+        // The overdue ping still runs: the next one fails without waiting for its deadline.
+        delay.store(0, Ordering::Relaxed);
+        let started = Instant::now();
+        let response = backend_handler.execute_request(ping_request(), None);
+        assert_eq!(
+            response.header.status,
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+        assert!(started.elapsed() < Duration::from_millis(50));
+
+        thread::sleep(Duration::from_millis(500));
+        let response = backend_handler.execute_request(ping_request(), None);
+        assert_eq!(response.header.status, ResponseStatus::Success);
+        assert!(!backend_handler.is_degraded());
+    }
+}
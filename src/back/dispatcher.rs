@@ -4,13 +4,24 @@
 //!
 //! The dispatcher's role is to direct requests to the provider they specify, if
 //! said provider is available on the system, thus acting as a multiplexer.
+//!
+//! Several instances of the same provider type can be registered, under different names. Requests
+//! addressed with a provider ID go to the first instance of that type, in priority order; the
+//! other instances can only be addressed by name.
+//!
+//! If automatic provider selection is enabled, requests for cryptographic operations addressed
+//! to the core provider, which can not execute them, are sent instead to the first provider, in
+//! priority order, able to serve them. Operations on a key go to the provider holding a key of
+//! the application with that name, if any.
+//!
+//! If failover is enabled, stateless operations addressed to a degraded provider are sent instead
+//! to the first healthy provider, in priority order, supporting them.
 use super::backend_handler::BackEndHandler;
 use crate::authenticators::Application;
-use log::trace;
+use log::{info, trace};
 use parsec_interface::requests::request::Request;
-use parsec_interface::requests::ProviderId;
+use parsec_interface::requests::{Opcode, ProviderId};
 use parsec_interface::requests::{Response, ResponseStatus};
-use std::collections::HashMap;
 use std::io::{Error, ErrorKind, Result};
 
 /// Dispatcher to backend
@@ -22,9 +33,19 @@ use std::io::{Error, ErrorKind, Result};
 /// the fields in the request header to the properties of the handlers.
 #[derive(Debug)]
 pub struct Dispatcher {
-    backends: HashMap<ProviderId, BackEndHandler>,
+    /// Backend handlers in priority order, with the name of their provider
+    backends: Vec<(String, BackEndHandler)>,
+    automatic_selection: bool,
+    failover: bool,
 }
 
+/// Operations which do not depend on the state of the provider executing them
+const STATELESS_OPCODES: [Opcode; 3] = [
+    Opcode::PsaGenerateRandom,
+    Opcode::PsaHashCompute,
+    Opcode::PsaHashCompare,
+];
+
 impl Dispatcher {
     /// Parses the `provider` field of the request header and attempts to find
     /// the backend handler to which the request must be dispatched.
@@ -32,54 +53,169 @@ impl Dispatcher {
     /// Returns either the response coming from the backend handler, or a response
     /// containing a status code consistent with the error encountered during
     /// processing.
-    pub fn dispatch_request(&self, request: Request, app: Option<Application>) -> Response {
+    pub fn dispatch_request(&self, mut request: Request, app: Option<Application>) -> Response {
         trace!("dispatch_request ingress");
-        if let Some(backend) = self.backends.get(&request.header.provider) {
-            if let Err(status) = backend.is_capable(&request) {
-                Response::from_request_header(request.header, status)
-            } else {
+        if self.automatic_selection
+            && request.header.provider == ProviderId::Core
+            && !request.header.opcode.is_core()
+        {
+            let header = request.header;
+            let mut response = match self.select_provider(request, &app) {
+                Ok((request, backend)) => Self::execute(backend, request, app),
+                Err(status) => Response::from_request_header(header, status),
+            };
+            // The response is sent on behalf of the provider the client addressed.
+            response.header.provider = ProviderId::Core;
+            return response;
+        }
+
+        if let Some((name, backend)) = self
+            .backends
+            .iter()
+            .find(|(_, backend)| backend.provider_id() == request.header.provider)
+        {
+            let opcode = request.header.opcode;
+            if self.failover && backend.is_degraded() && STATELESS_OPCODES.contains(&opcode) {
+                if let Some((other_name, other_backend)) =
+                    self.backends.iter().find(|(_, other_backend)| {
+                        other_backend.provider_id() != ProviderId::Core
+                            && !other_backend.is_degraded()
+                            && other_backend.supports_opcode(opcode)
+                    })
                 {
-                    let response = backend.execute_request(request, app);
-                    trace!("execute_request egress");
-                    response
+                    info!(
+                        "Provider {} is degraded, {:?} request sent to provider {} instead.",
+                        name, opcode, other_name
+                    );
+                    let provider = request.header.provider;
+                    request.header.provider = other_backend.provider_id();
+                    let mut response = Self::execute(other_backend, request, app);
+                    // The response is sent on behalf of the provider the client addressed.
+                    response.header.provider = provider;
+                    return response;
                 }
             }
+            Self::execute(backend, request, app)
+        } else {
+            Response::from_request_header(request.header, ResponseStatus::ProviderNotRegistered)
+        }
+    }
+
+    /// Dispatch the request to the provider with the given name, whatever the `provider` field of
+    /// its header. Requests for core operations are dispatched as usual.
+    pub fn dispatch_request_to(
+        &self,
+        provider_name: &str,
+        mut request: Request,
+        app: Option<Application>,
+    ) -> Response {
+        if request.header.opcode.is_core() {
+            return self.dispatch_request(request, app);
+        }
+        trace!("dispatch_request ingress");
+        if let Some((_, backend)) = self.backends.iter().find(|(name, _)| name == provider_name) {
+            request.header.provider = backend.provider_id();
+            Self::execute(backend, request, app)
         } else {
             Response::from_request_header(request.header, ResponseStatus::ProviderNotRegistered)
         }
     }
+
+    /// Execute the request on the backend, if it is capable of it.
+    fn execute(backend: &BackEndHandler, request: Request, app: Option<Application>) -> Response {
+        if let Err(status) = backend.is_capable(&request) {
+            Response::from_request_header(request.header, status)
+        } else {
+            let response = backend.execute_request(request, app);
+            trace!("execute_request egress");
+            response
+        }
+    }
+
+    /// Address the request to the first provider in priority order supporting its operation or,
+    /// for operations on a key, holding a key of the application with that name.
+    fn select_provider(
+        &self,
+        request: Request,
+        app: &Option<Application>,
+    ) -> std::result::Result<(Request, &BackEndHandler), ResponseStatus> {
+        let opcode = request.header.opcode;
+        let candidates: Vec<&(String, BackEndHandler)> = self
+            .backends
+            .iter()
+            .filter(|(_, backend)| {
+                backend.provider_id() != ProviderId::Core && backend.supports_opcode(opcode)
+            })
+            .collect();
+        let first = *candidates
+            .first()
+            .ok_or(ResponseStatus::PsaErrorNotSupported)?;
+
+        let (mut request, (name, backend)) = match app {
+            Some(app) if candidates.len() > 1 => {
+                let (request, key_name) = first.1.key_name(request)?;
+                let holder = key_name.and_then(|key_name| {
+                    candidates
+                        .iter()
+                        .find(|(_, backend)| backend.has_key(app, &key_name))
+                });
+                (request, holder.copied().unwrap_or(first))
+            }
+            _ => (request, first),
+        };
+
+        info!("{:?} request sent to provider {}.", opcode, name);
+        request.header.provider = backend.provider_id();
+        Ok((request, backend))
+    }
 }
 
 /// `Dispatcher` builder
 #[derive(Debug, Default)]
 pub struct DispatcherBuilder {
-    backends: Option<HashMap<ProviderId, BackEndHandler>>,
+    backends: Option<Vec<(String, BackEndHandler)>>,
+    automatic_selection: bool,
+    failover: bool,
 }
 
 impl DispatcherBuilder {
     /// Create a new Dispatcher builder
     pub fn new() -> Self {
-        DispatcherBuilder { backends: None }
+        DispatcherBuilder {
+            backends: None,
+            automatic_selection: false,
+            failover: false,
+        }
     }
 
     /// Add a BackEndHandler with a specific Provider ID to the dispatcher
-    pub fn with_backend(
-        mut self,
-        provider_id: ProviderId,
-        backend_handler: BackEndHandler,
-    ) -> Self {
+    ///
+    /// The provider is named after its ID.
+    pub fn with_backend(self, provider_id: ProviderId, backend_handler: BackEndHandler) -> Self {
+        self.with_named_backend(provider_id.to_string(), backend_handler)
+    }
+
+    /// Add a BackEndHandler of the provider with the given name to the dispatcher
+    ///
+    /// Backends are given priority in the order they are added.
+    pub fn with_named_backend(mut self, name: String, backend_handler: BackEndHandler) -> Self {
         let mut backends = self.backends.unwrap_or_default();
-        let _ = backends.insert(provider_id, backend_handler);
+        backends.push((name, backend_handler));
         self.backends = Some(backends);
 
         self
     }
 
-    /// Add multiple BackEndHandler to the dispatcher in one call
-    pub fn with_backends(mut self, new_backends: HashMap<ProviderId, BackEndHandler>) -> Self {
-        let mut backends = self.backends.unwrap_or_default();
-        backends.extend(new_backends);
-        self.backends = Some(backends);
+    /// Enable automatic provider selection
+    pub fn with_automatic_selection(mut self, automatic_selection: bool) -> Self {
+        self.automatic_selection = automatic_selection;
+
+        self
+    }
+
+    /// Enable failover of stateless operations away from degraded providers
+    pub fn with_failover(mut self, failover: bool) -> Self {
+        self.failover = failover;
 
         self
     }
@@ -90,6 +226,143 @@ impl DispatcherBuilder {
             backends: self
                 .backends
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "backends is missing"))?,
+            automatic_selection: self.automatic_selection,
+            failover: self.failover,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Dispatcher, DispatcherBuilder};
+    use crate::authenticators::{Application, ApplicationIdentity};
+    use crate::back::backend_handler::BackEndHandlerBuilder;
+    use crate::providers::mock::MockProvider;
+    use parsec_interface::operations::psa_algorithm::{AsymmetricSignature, Hash, SignHash};
+    use parsec_interface::operations::{psa_sign_hash, Convert, NativeOperation, NativeResult};
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+    use parsec_interface::requests::{
+        AuthType, BodyType, Opcode, ProviderId, Request, Response, ResponseStatus,
+    };
+    use std::sync::Arc;
+
+    const SIGN_HASH: AsymmetricSignature = AsymmetricSignature::RsaPkcs1v15Sign {
+        hash_alg: SignHash::Specific(Hash::Sha256),
+    };
+
+    /// Dispatcher with a Mbed Crypto provider and two PKCS 11 providers, the last one holding a
+    /// key named "key". Each provider signs with its position in priority order.
+    fn dispatcher() -> Dispatcher {
+        let mut dispatcher_builder = DispatcherBuilder::new().with_automatic_selection(true);
+        for (tag, &(name, id, key_name)) in [
+            ("mbed-crypto", ProviderId::MbedCrypto, None),
+            ("token-0", ProviderId::Pkcs11, None),
+            ("token-1", ProviderId::Pkcs11, Some("key")),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let mut provider = MockProvider::new(id).with_psa_sign_hash(move |_| {
+                Ok(psa_sign_hash::Result {
+                    signature: vec![tag as u8].into(),
+                })
+            });
+            if let Some(key_name) = key_name {
+                provider = provider.with_key(key_name);
+            }
+            let backend_handler = BackEndHandlerBuilder::new()
+                .with_provider(Arc::new(provider))
+                .with_converter(Box::from(ProtobufConverter {}))
+                .with_provider_id(id)
+                .with_content_type(BodyType::Protobuf)
+                .with_accept_type(BodyType::Protobuf)
+                .build()
+                .unwrap();
+            dispatcher_builder =
+                dispatcher_builder.with_named_backend(name.to_string(), backend_handler);
+        }
+        dispatcher_builder.build().unwrap()
+    }
+
+    fn sign_hash_request(provider: ProviderId, key_name: &str) -> Request {
+        let operation = NativeOperation::PsaSignHash(psa_sign_hash::Operation {
+            key_name: key_name.to_string(),
+            alg: SIGN_HASH,
+            hash: vec![0; 32].into(),
+        });
+        Request {
+            header: RequestHeader {
+                provider,
+                session: 0,
+                content_type: BodyType::Protobuf,
+                accept_type: BodyType::Protobuf,
+                auth_type: AuthType::UnixPeerCredentials,
+                opcode: Opcode::PsaSignHash,
+            },
+            body: ProtobufConverter {}.operation_to_body(operation).unwrap(),
+            auth: RequestAuth::new(Vec::new()),
+        }
+    }
+
+    fn app() -> Option<Application> {
+        Some(Application::new(
+            ApplicationIdentity::new("app".to_string(), AuthType::UnixPeerCredentials),
+            false,
+        ))
+    }
+
+    /// Tag of the provider which signed
+    fn signer(response: Response) -> Option<u8> {
+        assert_eq!(response.header.status, ResponseStatus::Success);
+        let result = ProtobufConverter {}
+            .body_to_result(response.body, Opcode::PsaSignHash)
+            .unwrap();
+        match result {
+            NativeResult::PsaSignHash(result) => result.signature.first().copied(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn automatic_provider_selection() {
+        let dispatcher = dispatcher();
+
+        // The key is held by the last provider in priority order.
+        let response =
+            dispatcher.dispatch_request(sign_hash_request(ProviderId::Core, "key"), app());
+        assert_eq!(response.header.provider, ProviderId::Core);
+        assert_eq!(signer(response), Some(2));
+        // Otherwise the first provider supporting the operation is chosen.
+        let response =
+            dispatcher.dispatch_request(sign_hash_request(ProviderId::Core, "other key"), app());
+        assert_eq!(signer(response), Some(0));
+    }
+
+    #[test]
+    fn provider_instances() {
+        let dispatcher = dispatcher();
+
+        // The provider ID addresses the first instance of its type.
+        let response =
+            dispatcher.dispatch_request(sign_hash_request(ProviderId::Pkcs11, "key"), app());
+        assert_eq!(signer(response), Some(1));
+        // Other instances are addressed by name.
+        let response = dispatcher.dispatch_request_to(
+            "token-1",
+            sign_hash_request(ProviderId::Core, "key"),
+            app(),
+        );
+        assert_eq!(response.header.provider, ProviderId::Pkcs11);
+        assert_eq!(signer(response), Some(2));
+        let response = dispatcher.dispatch_request_to(
+            "token-2",
+            sign_hash_request(ProviderId::Core, "key"),
+            app(),
+        );
+        assert_eq!(
+            response.header.status,
+            ResponseStatus::ProviderNotRegistered
+        );
+    }
+}
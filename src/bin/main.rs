@@ -40,19 +40,21 @@
 
 use anyhow::Result;
 use libc::{getuid, uid_t};
-use log::{info, trace};
+use log::{info, trace, warn};
+use parsec_service::front::listener::Listen;
+use parsec_service::front::poll::Poller;
 use parsec_service::utils::cli::Opts;
-use parsec_service::utils::{config::ServiceConfig, ServiceBuilder};
+use parsec_service::utils::config::{CoreSettings, ServiceConfig};
+use parsec_service::utils::correlation_id::CorrelationId;
+use parsec_service::utils::{ServiceBuilder, ServiceComponents};
 use signal_hook::{consts::SIGHUP, consts::SIGINT, consts::SIGTERM, flag};
-use std::io::{Error, ErrorKind};
+use std::io::{Error, ErrorKind, Write};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use std::time::Duration;
 use structopt::StructOpt;
-
-const MAIN_LOOP_DEFAULT_SLEEP: u64 = 10;
+use threadpool::ThreadPool;
 
 fn main() -> Result<()> {
     // Parsing the command line arguments.
@@ -65,6 +67,12 @@ fn main() -> Result<()> {
     let _ = flag::register(SIGTERM, kill_signal.clone())?;
     let _ = flag::register(SIGINT, kill_signal.clone())?;
     let _ = flag::register(SIGHUP, reload_signal.clone())?;
+    // The same signals also interrupt the wait for new connections so that they are acted upon
+    // without delay.
+    let poller = Poller::new()?;
+    for signal in &[SIGTERM, SIGINT, SIGHUP] {
+        poller.wake_on_signal(*signal)?;
+    }
 
     let mut config_file = ::std::fs::read_to_string(opts.config.clone()).map_err(|e| {
         Error::new(
@@ -94,14 +102,21 @@ fn main() -> Result<()> {
 
     log_setup(&config);
 
+    if config.core_settings.idle_listener_sleep_duration.is_some() {
+        warn!("The idle_listener_sleep_duration option is deprecated and ignored: the service now waits for new connections without polling.");
+    }
+
     info!("Parsec started. Configuring the service...");
 
-    let front_end_handler = ServiceBuilder::build_service(&config)?;
+    // Authenticators and providers are kept in there so that they can be reused when the
+    // configuration is reloaded.
+    let mut components = ServiceComponents::default();
+    let front_end_handler = ServiceBuilder::build_service_reusing(&config, &mut components)?;
     // Multiple threads can not just have a reference of the front end handler because they could
     // outlive the run function. It is needed to give them all ownership of the front end handler
     // through an Arc.
     let mut front_end_handler = Arc::from(front_end_handler);
-    let mut listener = ServiceBuilder::start_listener(config.listener)?;
+    let mut listeners = ServiceBuilder::start_listeners(&config.listener)?;
     let mut threadpool = ServiceBuilder::build_threadpool(config.core_settings.thread_pool_size);
 
     // Notify systemd that the daemon is ready, the start command will block until this point.
@@ -114,69 +129,117 @@ fn main() -> Result<()> {
             let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Reloading]);
             info!("SIGHUP signal received. Reloading the configuration...");
 
-            threadpool.join();
-
-            // Explicitely call drop now because otherwise Rust will drop these variables only
-            // after they have been overwritten, in which case some values/libraries might be
-            // initialized twice.
-            drop(front_end_handler);
-            drop(listener);
-            drop(threadpool);
-
             config_file = ::std::fs::read_to_string(opts.config.clone()).map_err(|e| {
                 Error::new(
                     e.kind(),
                     format!("Failed to read config file from path: {}", opts.config),
                 )
             })?;
-            config = toml::from_str(&config_file).map_err(|e| {
+            let new_config: ServiceConfig = toml::from_str(&config_file).map_err(|e| {
                 Error::new(
                     ErrorKind::InvalidInput,
                     format!("Failed to parse service configuration ({})", e),
                 )
             })?;
-            front_end_handler = Arc::from(ServiceBuilder::build_service(&config)?);
-            listener = ServiceBuilder::start_listener(config.listener)?;
-            threadpool = ServiceBuilder::build_threadpool(config.core_settings.thread_pool_size);
+
+            if components.retain_unchanged(&config, &new_config) {
+                info!("Some providers changed, waiting for the requests in progress to finish...");
+                threadpool.join();
+                // Explicitely call drop now because otherwise Rust will drop the front end
+                // handler only after it has been overwritten, in which case the providers that
+                // changed would be initialized twice.
+                drop(front_end_handler);
+            }
+            // Requests in progress keep using the previous front end handler, new connections are
+            // handled by the new one.
+            front_end_handler = Arc::from(ServiceBuilder::build_service_reusing(
+                &new_config,
+                &mut components,
+            )?);
+            listeners = ServiceBuilder::restart_listeners(
+                &config.listener,
+                listeners,
+                &new_config.listener,
+            )?;
+            if let Some(thread_pool_size) = new_config.core_settings.thread_pool_size {
+                threadpool.set_num_threads(thread_pool_size);
+            }
+            config = new_config;
 
             let _ = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]);
             info!("Parsec configuration reloaded.");
         }
 
-        if let Some(connection) = listener.accept() {
-            let front_end_handler = front_end_handler.clone();
-            threadpool.execute(move || {
-                front_end_handler.handle_request(connection);
-                trace!("handle_request egress");
-            });
-        } else {
-            ::std::thread::sleep(Duration::from_millis(
-                config
-                    .core_settings
-                    .idle_listener_sleep_duration
-                    .unwrap_or(MAIN_LOOP_DEFAULT_SLEEP),
-            ));
+        // Block until a client connects to one of the listeners or a signal is received.
+        let ready_listeners = poller.wait(
+            &listeners
+                .iter()
+                .map(|listener| listener.as_ref())
+                .collect::<Vec<&dyn Listen>>(),
+            None,
+        )?;
+
+        for index in ready_listeners {
+            if let Some(connection) = listeners[index].accept() {
+                if is_overloaded(&threadpool, &config.core_settings) {
+                    front_end_handler.reject_connection(connection);
+                    continue;
+                }
+
+                let front_end_handler = front_end_handler.clone();
+                threadpool.execute(move || {
+                    front_end_handler.handle_request(connection);
+                    trace!("handle_request egress");
+                });
+            }
         }
     }
 
     let _ = sd_notify::notify(true, &[sd_notify::NotifyState::Stopping]);
     info!("SIGTERM or SIGINT signal received. Shutting down Parsec, waiting for all threads to finish...");
     threadpool.join();
+    info!(
+        "{} connections were rejected because the service was overloaded.",
+        front_end_handler.rejected_connections()
+    );
     info!("Parsec is now terminated.");
 
     Ok(())
 }
 
+/// Check if the limits on the work queue are reached. Each connection is processed by one job of
+/// the thread pool.
+fn is_overloaded(threadpool: &ThreadPool, core_settings: &CoreSettings) -> bool {
+    let queued_connections = threadpool.queued_count();
+    let concurrent_connections = queued_connections + threadpool.active_count();
+
+    matches!(core_settings.max_queue_depth, Some(max) if queued_connections >= max)
+        || matches!(core_settings.max_concurrent_connections, Some(max) if concurrent_connections >= max)
+}
+
+/// Log records made while a request is processed are tagged with its correlation ID.
 fn log_setup(config: &ServiceConfig) {
     let mut env_log_builder = env_logger::builder();
 
     if let Some(level) = config.core_settings.log_level {
         let _ = env_log_builder.filter_level(level);
     }
-    if let Some(true) = config.core_settings.log_timestamp {
-        let _ = env_log_builder.format_timestamp_millis();
-    } else {
-        let _ = env_log_builder.format_timestamp(None);
-    }
+    let log_timestamp = config.core_settings.log_timestamp == Some(true);
+    let _ = env_log_builder.format(move |buf, record| {
+        write!(buf, "[")?;
+        if log_timestamp {
+            write!(buf, "{} ", buf.timestamp_millis())?;
+        }
+        write!(
+            buf,
+            "{:<5} {}",
+            buf.default_styled_level(record.level()),
+            record.module_path().unwrap_or_default()
+        )?;
+        if let Some(correlation_id) = CorrelationId::current() {
+            write!(buf, " {}", correlation_id)?;
+        }
+        writeln!(buf, "] {}", record.args())
+    });
     env_log_builder.init();
 }
@@ -0,0 +1,189 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Replay tool for Parsec capture files
+//!
+//! Feeds the requests recorded by the Parsec service, with the `capture_file` option, back through
+//! a service built from a given configuration and compares the responses with the recorded ones.
+#![deny(
+    nonstandard_style,
+    dead_code,
+    improper_ctypes,
+    non_shorthand_field_patterns,
+    no_mangle_generic_items,
+    overflowing_literals,
+    path_statements,
+    patterns_in_fns_without_body,
+    private_in_public,
+    unconditional_recursion,
+    unused,
+    unused_allocation,
+    unused_comparisons,
+    unused_parens,
+    while_true,
+    missing_debug_implementations,
+    missing_docs,
+    trivial_casts,
+    trivial_numeric_casts,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_qualifications,
+    unused_results,
+    missing_copy_implementations
+)]
+// This one is hard to avoid.
+#![allow(clippy::multiple_crate_versions)]
+
+use anyhow::{Context, Result};
+use libc::{getgid, getpid, getuid};
+use parsec_interface::requests::request::RequestAuth;
+use parsec_interface::requests::{AuthType, Request, Response};
+use parsec_service::front::front_end::FrontEndHandler;
+use parsec_service::front::listener::{Connection, ConnectionMetadata, Protocol};
+use parsec_service::utils::cli::ReplayOpts;
+use parsec_service::utils::config::ServiceConfig;
+use parsec_service::utils::process;
+use parsec_service::utils::ServiceBuilder;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Error, ErrorKind, Read, Write};
+use std::sync::{Arc, Mutex};
+use structopt::StructOpt;
+
+/// The capture file is trusted: the size of the messages it contains is not limited.
+const NO_LIMIT: usize = usize::MAX;
+
+fn main() -> Result<()> {
+    let opts = ReplayOpts::from_args();
+
+    let config_file = std::fs::read_to_string(&opts.config)
+        .with_context(|| format!("Failed to read config file from path: {}", opts.config))?;
+    let mut config: ServiceConfig = toml::from_str(&config_file).map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Failed to parse service configuration ({})", e),
+        )
+    })?;
+    // The replayed requests must not be recorded again.
+    config.core_settings.capture_file = None;
+
+    let mut env_log_builder = env_logger::builder();
+    if let Some(level) = config.core_settings.log_level {
+        let _ = env_log_builder.filter_level(level);
+    }
+    env_log_builder.init();
+
+    let front_end_handler = ServiceBuilder::build_service(&config)?;
+
+    let mut capture = BufReader::new(
+        File::open(&opts.capture)
+            .with_context(|| format!("Failed to open the capture file {}", opts.capture))?,
+    );
+    let mut replayed = 0;
+    let mut different = 0;
+    while !capture.fill_buf()?.is_empty() {
+        let request =
+            Request::read_from_stream(&mut capture, NO_LIMIT).context("Invalid capture file")?;
+        let recorded =
+            Response::read_from_stream(&mut capture, NO_LIMIT).context("Invalid capture file")?;
+
+        let opcode = request.header.opcode;
+        let provider = request.header.provider;
+        let response = replay(&front_end_handler, request)?;
+        replayed += 1;
+
+        if response == recorded {
+            println!(
+                "#{} {:?} on {}: {:?}",
+                replayed, opcode, provider, response.header.status
+            );
+        } else {
+            different += 1;
+            println!(
+                "#{} {:?} on {}: {:?}, recorded {:?}{}",
+                replayed,
+                opcode,
+                provider,
+                response.header.status,
+                recorded.header.status,
+                if response.body == recorded.body {
+                    ""
+                } else {
+                    " (different body)"
+                }
+            );
+        }
+    }
+
+    println!(
+        "{} requests replayed, {} with a different response.",
+        replayed, different
+    );
+    if different != 0 {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Send a recorded request through the front end handler and return its response.
+fn replay(front_end_handler: &FrontEndHandler, mut request: Request) -> Result<Response> {
+    let metadata = if request.header.auth_type == AuthType::UnixPeerCredentials {
+        // Safe as these calls can not fail.
+        let (uid, gid, pid) = unsafe { (getuid(), getgid(), getpid()) };
+        request.auth = RequestAuth::new(uid.to_le_bytes().to_vec());
+        Some(ConnectionMetadata::UnixPeerCredentials {
+            uid,
+            gid,
+            pid: Some(pid),
+            start_time: process::start_time(pid).ok(),
+        })
+    } else {
+        // The authentication field was redacted: the request can only be replayed without
+        // authentication.
+        request.header.auth_type = AuthType::NoAuth;
+        request.auth = RequestAuth::new(Vec::new());
+        None
+    };
+
+    let mut raw_request = Vec::new();
+    request.write_to_stream(&mut raw_request)?;
+    let raw_response = Arc::new(Mutex::new(Vec::new()));
+    front_end_handler.handle_request(Connection {
+        stream: Box::new(ReplayStream {
+            request: Cursor::new(raw_request),
+            response: raw_response.clone(),
+        }),
+        metadata,
+        permitted_authenticators: None,
+        protocol: Protocol::Parsec,
+        handshake: None,
+    });
+
+    let raw_response = raw_response.lock().expect("Response lock poisoned");
+    Response::read_from_stream(&mut &raw_response[..], NO_LIMIT)
+        .context("The service did not send a valid response")
+}
+
+/// Stream reading a recorded request and keeping the response written back
+struct ReplayStream {
+    request: Cursor<Vec<u8>>,
+    response: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Read for ReplayStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.request.read(buf)
+    }
+}
+
+impl Write for ReplayStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.response
+            .lock()
+            .expect("Response lock poisoned")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -5,22 +5,28 @@
 //! Expose Parsec functionality using Unix domain sockets as an IPC layer.
 //! The local socket is created at a predefined location.
 use super::listener;
+use crate::utils::process;
 use anyhow::{Context, Result};
 use listener::Listen;
-use listener::{Connection, ConnectionMetadata};
+use listener::{Connection, ConnectionMetadata, Protocol};
 use log::{error, warn};
+use std::ffi::CString;
 use std::fs;
 use std::fs::Permissions;
 use std::io::{Error, ErrorKind};
-use std::os::unix::fs::FileTypeExt;
+use std::os::unix::ffi::OsStrExt;
 use std::os::unix::fs::PermissionsExt;
-use std::os::unix::io::FromRawFd;
-use std::os::unix::io::RawFd;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::UnixListener;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 static DEFAULT_SOCKET_PATH: &str = "/run/parsec/parsec.sock";
+/// Default permissions of the socket, allowing clients of any user to connect
+const DEFAULT_SOCKET_MODE: u32 = 0o666;
+/// Restricted deletion flag of directories: only the owner of a file can remove or rename it
+const STICKY_BIT: u32 = 0o1000;
 
 /// Unix Domain Socket IPC manager
 ///
@@ -35,12 +41,53 @@ pub struct DomainSocketListener {
 
 impl DomainSocketListener {
     /// Initialise the connection to the Unix socket.
-    pub fn new(timeout: Duration, socket_path: PathBuf) -> Result<Self> {
+    ///
+    /// If the socket is created by the service, its permissions are set to `socket_mode` and it is
+    /// owned by `socket_group`, if given. If `socket_directory_mode` is given, the directory
+    /// containing the socket is created if needed, its permissions are set to that mode and it is
+    /// owned by `socket_group`, if given.
+    ///
+    /// In all cases, including socket activation, the service refuses to start if the socket or
+    /// its parent directories could be modified by untrusted users.
+    pub fn new(
+        timeout: Duration,
+        socket_path: PathBuf,
+        socket_mode: u32,
+        socket_group: Option<String>,
+        socket_directory_mode: Option<u32>,
+    ) -> Result<Self> {
+        let socket_gid = match socket_group {
+            Some(socket_group) => Some(group_id(&socket_group)?),
+            None => None,
+        };
+
         // If Parsec was service activated or not started under systemd, this
         // will return `0`. `1` will be returned in case Parsec is socket activated.
         let listeners: Vec<RawFd> = sd_notify::listen_fds()?.collect();
         let listener = match listeners.len() {
             0 => {
+                let socket_directory = socket_directory(&socket_path);
+                if let Some(socket_directory_mode) = socket_directory_mode {
+                    fs::DirBuilder::new()
+                        .recursive(true)
+                        .mode(socket_directory_mode)
+                        .create(&socket_directory)
+                        .with_context(|| {
+                            format!(
+                                "Failed to create the socket directory {}",
+                                socket_directory.display()
+                            )
+                        })?;
+                    fs::set_permissions(
+                        &socket_directory,
+                        Permissions::from_mode(socket_directory_mode),
+                    )?;
+                    if let Some(socket_gid) = socket_gid {
+                        set_group(&socket_directory, socket_gid)?;
+                    }
+                }
+                check_directory_permissions(&socket_directory)?;
+
                 if socket_path.exists() {
                     let meta = fs::metadata(&socket_path)?;
                     if meta.file_type().is_socket() {
@@ -63,10 +110,13 @@ impl DomainSocketListener {
                 })?;
                 listener.set_nonblocking(true)?;
 
-                // Set the socket's permission to 666 to allow clients of different user to
-                // connect.
-                let permissions = Permissions::from_mode(0o666);
-                fs::set_permissions(socket_path, permissions)?;
+                // By default, set the socket's permission to 666 to allow clients of different
+                // user to connect.
+                let permissions = Permissions::from_mode(socket_mode);
+                fs::set_permissions(&socket_path, permissions)?;
+                if let Some(socket_gid) = socket_gid {
+                    set_group(&socket_path, socket_gid)?;
+                }
 
                 listener
             }
@@ -76,8 +126,24 @@ impl DomainSocketListener {
                 let nfd = listeners[0];
                 // Safe as listen_fds gives us the information that one file descriptor was
                 // received and its value starts from SD_LISTEN_FDS_START.
-                unsafe { UnixListener::from_raw_fd(nfd) }
-                // Expect the socket created by systemd to be 666 on permissions.
+                let listener = unsafe { UnixListener::from_raw_fd(nfd) };
+                // The permissions of the socket created by systemd are the ones set in the
+                // socket unit.
+                if socket_gid.is_some() || socket_directory_mode.is_some() {
+                    warn!("The socket was created by systemd: the socket group and directory permissions configured are ignored. They must be set in the socket unit instead.");
+                }
+
+                let socket_path = listener
+                    .local_addr()?
+                    .as_pathname()
+                    .map(PathBuf::from)
+                    .ok_or_else(|| {
+                        error!("The socket received from systemd is not bound to a path.");
+                        Error::new(ErrorKind::InvalidData, "socket not bound to a path")
+                    })?;
+                check_directory_permissions(&socket_directory(&socket_path))?;
+
+                listener
             }
             n => {
                 error!(
@@ -96,6 +162,97 @@ impl DomainSocketListener {
     }
 }
 
+/// Directory containing the socket file.
+fn socket_directory(socket_path: &Path) -> PathBuf {
+    match socket_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Check that the directory containing the socket, and all of its ancestors, can only be modified
+/// by trusted users: root and the user running the service. Otherwise, an untrusted user could
+/// replace the socket with its own and impersonate the service.
+///
+/// The permissions on the socket file itself are not checked: being able to write to the socket
+/// only gives the right to connect to it.
+fn check_directory_permissions(socket_directory: &Path) -> Result<()> {
+    // Safe as these calls can not fail.
+    let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    let socket_directory = fs::canonicalize(socket_directory).with_context(|| {
+        format!(
+            "Failed to find the socket directory {}",
+            socket_directory.display()
+        )
+    })?;
+
+    for directory in socket_directory.ancestors() {
+        let meta = fs::metadata(directory)?;
+        let mode = meta.mode();
+        let sticky = mode & STICKY_BIT != 0;
+
+        let reason = if meta.uid() != 0 && meta.uid() != euid {
+            "is owned by another user"
+        } else if mode & 0o002 != 0 && !sticky {
+            "is writable by all users"
+        } else if mode & 0o020 != 0 && meta.gid() != 0 && meta.gid() != egid && !sticky {
+            "is writable by the users of another group"
+        } else {
+            continue;
+        };
+
+        error!(
+            "Insecure socket location: the directory {} {}. Untrusted users could replace the socket.",
+            directory.display(),
+            reason
+        );
+        return Err(Error::new(ErrorKind::PermissionDenied, "insecure socket directory").into());
+    }
+
+    Ok(())
+}
+
+/// Find the ID of the group with the given name.
+fn group_id(group_name: &str) -> Result<libc::gid_t> {
+    let c_group_name = CString::new(group_name)?;
+    // Safe as all the fields of the group structure are either integers or pointers.
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer: Vec<libc::c_char> = vec![0; 4096];
+    let mut result = std::ptr::null_mut();
+    // Safe as the pointers given describe the structures and buffer above, which outlive the call.
+    let ret = unsafe {
+        libc::getgrnam_r(
+            c_group_name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        return Err(Error::from_raw_os_error(ret))
+            .with_context(|| format!("Failed to look up the group {}", group_name));
+    }
+    if result.is_null() {
+        error!("The socket group {} does not exist.", group_name);
+        return Err(Error::new(ErrorKind::InvalidInput, "socket group not found").into());
+    }
+
+    Ok(group.gr_gid)
+}
+
+/// Change the group owning the file at `path`, keeping its owner.
+fn set_group(path: &Path, gid: libc::gid_t) -> Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    // Safe as the path is a valid C string. Passing -1 as the user ID leaves it unchanged.
+    if unsafe { libc::chown(c_path.as_ptr(), libc::uid_t::MAX, gid) } != 0 {
+        return Err(Error::last_os_error())
+            .with_context(|| format!("Failed to change the group of {}", path.display()));
+    }
+
+    Ok(())
+}
+
 impl Listen for DomainSocketListener {
     fn set_timeout(&mut self, duration: Duration) {
         self.timeout = duration;
@@ -130,7 +287,11 @@ impl Listen for DomainSocketListener {
                             uid: ucred.uid,
                             gid: ucred.gid,
                             pid: ucred.pid,
+                            start_time: ucred.pid.and_then(|pid| process::start_time(pid).ok()),
                         }),
+                        permitted_authenticators: None,
+                        protocol: Protocol::Parsec,
+                        handshake: None,
                     })
                 }
             }
@@ -146,11 +307,20 @@ impl Listen for DomainSocketListener {
     }
 }
 
+impl AsRawFd for DomainSocketListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
 /// Builder for `DomainSocketListener`
 #[derive(Clone, Debug, Default)]
 pub struct DomainSocketListenerBuilder {
     timeout: Option<Duration>,
     socket_path: Option<PathBuf>,
+    socket_mode: Option<u32>,
+    socket_group: Option<String>,
+    socket_directory_mode: Option<u32>,
 }
 
 impl DomainSocketListenerBuilder {
@@ -159,6 +329,9 @@ impl DomainSocketListenerBuilder {
         DomainSocketListenerBuilder {
             timeout: None,
             socket_path: None,
+            socket_mode: None,
+            socket_group: None,
+            socket_directory_mode: None,
         }
     }
 
@@ -174,6 +347,25 @@ impl DomainSocketListenerBuilder {
         self
     }
 
+    /// Specify the permissions of the socket file. Defaults to `0o666`.
+    pub fn with_socket_mode(mut self, socket_mode: Option<u32>) -> Self {
+        self.socket_mode = socket_mode;
+        self
+    }
+
+    /// Specify the name of the group owning the socket file
+    pub fn with_socket_group(mut self, socket_group: Option<String>) -> Self {
+        self.socket_group = socket_group;
+        self
+    }
+
+    /// Specify the permissions of the directory containing the socket file. They are left
+    /// unchanged if not set.
+    pub fn with_socket_directory_mode(mut self, socket_directory_mode: Option<u32>) -> Self {
+        self.socket_directory_mode = socket_directory_mode;
+        self
+    }
+
     /// Build the builder into the listener
     pub fn build(self) -> Result<DomainSocketListener> {
         DomainSocketListener::new(
@@ -183,6 +375,9 @@ impl DomainSocketListenerBuilder {
             })?,
             self.socket_path
                 .unwrap_or_else(|| DEFAULT_SOCKET_PATH.into()),
+            self.socket_mode.unwrap_or(DEFAULT_SOCKET_MODE),
+            self.socket_group,
+            self.socket_directory_mode,
         )
     }
 }
@@ -311,3 +506,28 @@ pub mod peer_credentials {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::check_directory_permissions;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn insecure_socket_directory() {
+        let directory = std::env::temp_dir().join("parsec-socket-directory-test");
+        fs::create_dir_all(&directory).unwrap();
+
+        fs::set_permissions(&directory, Permissions::from_mode(0o755)).unwrap();
+        check_directory_permissions(&directory).unwrap();
+
+        fs::set_permissions(&directory, Permissions::from_mode(0o777)).unwrap();
+        let _ = check_directory_permissions(&directory).unwrap_err();
+
+        // Like /tmp, a world-writable directory with the sticky bit is accepted.
+        fs::set_permissions(&directory, Permissions::from_mode(0o1777)).unwrap();
+        check_directory_permissions(&directory).unwrap();
+
+        fs::remove_dir(&directory).unwrap();
+    }
+}
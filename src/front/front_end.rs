@@ -4,16 +4,27 @@
 //!
 //! The front end handler accepts streams of data that it can use to read requests,
 //! pass them to the rest of the service and write the responses back.
-use crate::authenticators::Authenticate;
+use crate::authenticators::{Application, Authenticate};
 use crate::back::dispatcher::Dispatcher;
 use crate::front::listener::Connection;
+#[cfg(feature = "http-gateway")]
+use crate::front::listener::Protocol;
+use crate::front::rate_limiter::RateLimiter;
+use crate::front::recorder::{Recorder, RecordingReader};
+use crate::utils::correlation_id::{CorrelationId, CorrelationScope};
 use derivative::Derivative;
-use log::{info, trace};
-use parsec_interface::requests::AuthType;
+use log::{error, info, trace};
+use parsec_interface::requests::request::RequestAuth;
 use parsec_interface::requests::ResponseStatus;
+use parsec_interface::requests::{AuthType, Opcode};
 use parsec_interface::requests::{Request, Response};
+use parsec_interface::secrecy::ExposeSecret;
 use std::collections::HashMap;
-use std::io::{Error, ErrorKind, Result};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
 
 /// Read and verify request from IPC stream
 ///
@@ -27,24 +38,121 @@ pub struct FrontEndHandler {
     dispatcher: Dispatcher,
     // Send and Sync are required for Arc<FrontEndHandler> to be Send.
     #[derivative(Debug = "ignore")]
-    authenticators: HashMap<AuthType, Box<dyn Authenticate + Send + Sync>>,
+    authenticators: HashMap<AuthType, Arc<dyn Authenticate + Send + Sync>>,
     /// Value used to limit the size of the request body to be that can be accepted by the service.
     body_len_limit: usize,
+    /// Maximum number of requests served on one connection. Equal to 1 unless persistent
+    /// connections are enabled.
+    max_requests_per_connection: usize,
+    /// Time after which an idle persistent connection is closed.
+    connection_idle_timeout: Option<Duration>,
+    /// Number of connections rejected because the service was overloaded, shared with the front
+    /// end handlers built before and after this one.
+    rejected_connections: Arc<AtomicUsize>,
+    /// Limits on the rate of requests of each application, if any.
+    rate_limiter: Option<RateLimiter>,
+    /// Recorder of the requests and responses, if capturing them.
+    recorder: Option<Recorder>,
 }
 
 impl FrontEndHandler {
     /// Handle new connections on the underlying IPC mechanism.
     ///
     /// Unmarshalls a request from the stream, passes it to the dispatcher and marshalls
-    /// the response back onto the stream.
+    /// the response back onto the stream. If persistent connections are enabled, the same is done
+    /// for the following requests sent on the connection, until the client closes it, stays idle
+    /// for longer than the idle timeout or reaches the maximum number of requests per connection.
     ///
     /// If an error occurs during (un)marshalling; no operation will be performed, an error will be logged
     /// and the method will return.
+    ///
+    /// The handshake of the connection, if it has one, is completed first. Connections accepted by
+    /// HTTP listeners are then handed over to the HTTP gateway.
     pub fn handle_request(&self, mut connection: Connection) {
         trace!("handle_request ingress");
+        if !connection.complete_handshake() {
+            return;
+        }
+        #[cfg(feature = "http-gateway")]
+        if connection.protocol == Protocol::Http {
+            return super::http_gateway::handle_connection(self, connection);
+        }
+        let mut auth_cache = None;
+
+        for request_index in 0..self.max_requests_per_connection {
+            let next_request_started = if request_index == 0 {
+                None
+            } else {
+                match self.wait_for_next_request(&mut connection) {
+                    Some(first_byte) => Some(first_byte),
+                    None => break,
+                }
+            };
+
+            if !self.handle_single_request(&mut connection, next_request_started, &mut auth_cache) {
+                break;
+            }
+        }
+    }
+
+    /// Reject a new connection because the service is overloaded.
+    ///
+    /// A response with the `PsaErrorInsufficientMemory` status is sent straight away, without
+    /// reading the request, so that the client does not wait for its request to be processed.
+    /// Connections with a handshake are closed without a response: the handshake could block.
+    /// Connections accepted by HTTP listeners get a `503 Service Unavailable` HTTP response.
+    pub fn reject_connection(&self, mut connection: Connection) {
+        let rejected_connections = self.rejected_connections.fetch_add(1, Ordering::Relaxed) + 1;
+        info!(
+            "The service is overloaded, rejecting a new connection ({} rejected so far).",
+            rejected_connections
+        );
+        if connection.handshake.is_some() {
+            return;
+        }
+        #[cfg(feature = "http-gateway")]
+        if connection.protocol == Protocol::Http {
+            return super::http_gateway::reject_connection(connection);
+        }
+
+        let response = Response::from_status(ResponseStatus::PsaErrorInsufficientMemory);
+        if let Err(err) = response.write_to_stream(&mut connection.stream) {
+            format_error!("Failed to send the busy response", err);
+        }
+    }
+
+    /// Number of connections rejected because the service was overloaded.
+    pub fn rejected_connections(&self) -> usize {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    /// Read, execute and answer one request from the connection. If `first_byte` is set, it has
+    /// already been read from the stream and is the first byte of the request.
+    ///
+    /// The request is given a new correlation ID, tagging all the log records made while it is
+    /// processed.
+    ///
+    /// Returns `false` if the connection can not be used for further requests.
+    fn handle_single_request(
+        &self,
+        connection: &mut Connection,
+        first_byte: Option<u8>,
+        auth_cache: &mut Option<CachedAuthentication>,
+    ) -> bool {
+        let _correlation_scope = CorrelationScope::enter(CorrelationId::new());
         // Read bytes from stream
         // De-Serialise bytes into a request
-        let request = match Request::read_from_stream(&mut connection.stream, self.body_len_limit) {
+        let first_byte = first_byte.map(|first_byte| [first_byte]);
+        let mut reader = RecordingReader::new(
+            first_byte
+                .as_ref()
+                .map_or(&[][..], |first_byte| &first_byte[..])
+                .chain(&mut connection.stream),
+            self.recorder.is_some(),
+        );
+        let request = Request::read_from_stream(&mut reader, self.body_len_limit);
+        let raw_request = reader.into_recorded();
+        let request = match request {
             Ok(request) => request,
             Err(status) => {
                 format_error!("Failed to read request", status);
@@ -56,56 +164,39 @@ impl FrontEndHandler {
                 if let Err(status) = response.write_to_stream(&mut connection.stream) {
                     format_error!("Failed to write response", status);
                 }
-                return;
-            }
-        };
-
-        // Check if the request was sent without authentication
-        let (app, err_response) = if AuthType::NoAuth == request.header.auth_type {
-            (None, None)
-        // Otherwise find an authenticator that is capable to authenticate the request
-        } else if let Some(authenticator) = self.authenticators.get(&request.header.auth_type) {
-            // Authenticate the request
-            match authenticator.authenticate(&request.auth, connection.metadata) {
-                // Send the request to the dispatcher
-                // Get a response back
-                Ok(app) => (Some(app), None),
-                Err(status) => (
-                    None,
-                    Some(Response::from_request_header(request.header, status)),
-                ),
+                return false;
             }
-        } else {
-            (
-                None,
-                Some(Response::from_request_header(
-                    request.header,
-                    ResponseStatus::AuthenticatorNotRegistered,
-                )),
-            )
         };
 
-        let response = if let Some(err_response) = err_response {
-            err_response
-        } else {
-            if crate::utils::GlobalConfig::log_error_details() {
-                if let Some(app) = &app.as_ref() {
-                    info!(
-                        "New request received from application name \"{}\"",
-                        app.identity().name()
-                    )
-                } else {
-                    info!("New request received without authentication")
-                }
-            };
-            let response = self.dispatcher.dispatch_request(request, app.clone());
-            trace!("dispatch_request egress");
-            response
-        };
+        let opcode = request.header.opcode;
+        let body_len = request.body.len();
+        let auth_len = request.auth.buffer.expose_secret().len();
+        let (response, app) = self.authenticate_and_dispatch(request, connection, auth_cache);
 
         // Serialise the response into bytes
         // Write bytes to stream
-        match response.write_to_stream(&mut connection.stream) {
+        let written = match (&self.recorder, raw_request) {
+            (Some(recorder), Some(raw_request)) => {
+                let response_body_len = response.body.len();
+                let mut raw_response = Vec::new();
+                response.write_to_stream(&mut raw_response).and_then(|_| {
+                    recorder.record(
+                        opcode,
+                        raw_request,
+                        body_len,
+                        auth_len,
+                        &raw_response,
+                        response_body_len,
+                    );
+                    connection
+                        .stream
+                        .write_all(&raw_response)
+                        .map_err(ResponseStatus::from)
+                })
+            }
+            _ => response.write_to_stream(&mut connection.stream),
+        };
+        match written {
             Ok(_) => {
                 if crate::utils::GlobalConfig::log_error_details() {
                     if let Some(app) = app {
@@ -117,8 +208,265 @@ impl FrontEndHandler {
                         info!("Response sent back from request without authentication");
                     }
                 }
+                true
+            }
+            Err(err) => {
+                format_error!("Failed to send response", err);
+                false
+            }
+        }
+    }
+
+    /// Authenticate a request received outside of the Parsec wire protocol, on the given
+    /// connection, pass it to the dispatcher and return the response. If `provider_name` is set,
+    /// the request is executed by the provider with that name instead of the one in its header.
+    ///
+    /// Listeners, authenticators and rate limits are enforced as for any other request.
+    pub fn process_request(
+        &self,
+        request: Request,
+        provider_name: Option<&str>,
+        connection: &Connection,
+    ) -> Response {
+        match self.authenticate(&request, connection, &mut None) {
+            Ok(app) => self.dispatch(request, app, provider_name),
+            Err(status) => Response::from_request_header(request.header, status),
+        }
+    }
+
+    /// Whether an authenticator of the given type is enabled.
+    pub fn has_authenticator(&self, auth_type: AuthType) -> bool {
+        self.authenticators.contains_key(&auth_type)
+    }
+
+    /// Maximum size of the request bodies accepted by the service.
+    pub fn body_len_limit(&self) -> usize {
+        self.body_len_limit
+    }
+
+    /// Authenticate the request and pass it to the dispatcher.
+    ///
+    /// Returns the response to send back and the application which sent the request, if it was
+    /// authenticated.
+    fn authenticate_and_dispatch(
+        &self,
+        request: Request,
+        connection: &Connection,
+        auth_cache: &mut Option<CachedAuthentication>,
+    ) -> (Response, Option<Application>) {
+        match self.authenticate(&request, connection, auth_cache) {
+            Ok(app) => (self.dispatch(request, app.clone(), None), app),
+            Err(status) => (Response::from_request_header(request.header, status), None),
+        }
+    }
+
+    /// Authenticate a batch of requests received outside of the Parsec wire protocol once, pass
+    /// them to the dispatcher in turn and return their responses, in the same order.
+    ///
+    /// Only the first request is authenticated: the other ones must carry the same authentication,
+    /// or they fail with `ResponseStatus::AuthenticationError`. Rate limits apply to each request.
+    /// `provider_name` is used as for `process_request`.
+    pub fn process_batch(
+        &self,
+        requests: Vec<Request>,
+        provider_name: Option<&str>,
+        connection: &Connection,
+    ) -> Vec<Response> {
+        let mut requests = requests.into_iter();
+        let first = match requests.next() {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        let auth_type = first.header.auth_type;
+        let auth = Zeroizing::new(first.auth.buffer.expose_secret().clone());
+        let app = self.authenticate(&first, connection, &mut None);
+
+        std::iter::once(first)
+            .chain(requests)
+            .map(|request| {
+                if request.header.auth_type != auth_type
+                    || *request.auth.buffer.expose_secret() != *auth
+                {
+                    error!("The requests of a batch must all carry the same authentication.");
+                    Response::from_request_header(
+                        request.header,
+                        ResponseStatus::AuthenticationError,
+                    )
+                } else {
+                    match &app {
+                        Ok(app) => self.dispatch(request, app.clone(), provider_name),
+                        Err(status) => Response::from_request_header(request.header, *status),
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// Authenticate the request, reusing or filling the authentication cache of the connection.
+    ///
+    /// Returns the application which sent the request, or `None` if it was sent without
+    /// authentication.
+    fn authenticate(
+        &self,
+        request: &Request,
+        connection: &Connection,
+        auth_cache: &mut Option<CachedAuthentication>,
+    ) -> std::result::Result<Option<Application>, ResponseStatus> {
+        // Check if the request was sent without authentication
+        if AuthType::NoAuth == request.header.auth_type {
+            Ok(None)
+        // Check if the authenticator can be used on this connection
+        } else if matches!(
+            &connection.permitted_authenticators,
+            Some(permitted) if !permitted.contains(&request.header.auth_type)
+        ) {
+            error!(
+                "Authenticator {:?} is not permitted on the listener the request was received from.",
+                request.header.auth_type
+            );
+            Err(ResponseStatus::AuthenticatorNotRegistered)
+        // Reuse the result of a previous authentication on this connection, if possible
+        } else if let Some(app) = auth_cache
+            .as_ref()
+            .and_then(|cache| cache.get(request.header.auth_type, &request.auth))
+        {
+            Ok(Some(app))
+        // Otherwise find an authenticator that is capable to authenticate the request
+        } else if let Some(authenticator) = self.authenticators.get(&request.header.auth_type) {
+            // Authenticate the request
+            let app = authenticator.authenticate(&request.auth, connection.metadata.clone())?;
+            if self.max_requests_per_connection > 1 && authenticator.is_connection_bound() {
+                *auth_cache = Some(CachedAuthentication::new(
+                    request.header.auth_type,
+                    &request.auth,
+                    app.clone(),
+                ));
+            }
+            Ok(Some(app))
+        } else {
+            Err(ResponseStatus::AuthenticatorNotRegistered)
+        }
+    }
+
+    /// Pass an authenticated request to the dispatcher, if the rate limit of the application
+    /// allows it, and return the response. The request is sent to the provider with the given
+    /// name, if set.
+    fn dispatch(
+        &self,
+        request: Request,
+        app: Option<Application>,
+        provider_name: Option<&str>,
+    ) -> Response {
+        if !self.rate_limit_allows(&app, request.header.opcode) {
+            return Response::from_request_header(
+                request.header,
+                ResponseStatus::PsaErrorNotPermitted,
+            );
+        }
+        if crate::utils::GlobalConfig::log_error_details() {
+            if let Some(app) = &app.as_ref() {
+                info!(
+                    "New request received from application name \"{}\"",
+                    app.identity().name()
+                )
+            } else {
+                info!("New request received without authentication")
+            }
+        };
+        let response = match provider_name {
+            Some(provider_name) => self
+                .dispatcher
+                .dispatch_request_to(provider_name, request, app),
+            None => self.dispatcher.dispatch_request(request, app),
+        };
+        trace!("dispatch_request egress");
+        response
+    }
+
+    /// Check that the application did not exceed its rate limit for the opcode.
+    fn rate_limit_allows(&self, app: &Option<Application>, opcode: Opcode) -> bool {
+        let rate_limiter = match &self.rate_limiter {
+            Some(rate_limiter) => rate_limiter,
+            None => return true,
+        };
+        let identity = app.as_ref().map(|app| app.identity());
+        if rate_limiter.allow(identity, opcode) {
+            true
+        } else {
+            match identity {
+                Some(identity) => info!(
+                    "Rate limit of application name \"{}\" exceeded for {:?}",
+                    identity.name(),
+                    opcode
+                ),
+                None => info!(
+                    "Rate limit of requests without authentication exceeded for {:?}",
+                    opcode
+                ),
+            }
+            false
+        }
+    }
+
+    /// Wait for the client to start sending a new request on a persistent connection.
+    ///
+    /// Every read on the stream is bounded by the listener timeout: the idle timeout is checked
+    /// each time such a read times out.
+    ///
+    /// Returns the first byte of the new request, or `None` if the connection was closed by the
+    /// client, stayed idle for too long or failed.
+    fn wait_for_next_request(&self, connection: &mut Connection) -> Option<u8> {
+        let idle_timeout = self.connection_idle_timeout?;
+        let idle_since = Instant::now();
+        let mut first_byte = [0];
+
+        loop {
+            match connection.stream.read(&mut first_byte) {
+                Ok(0) => return None,
+                Ok(_) => return Some(first_byte[0]),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err)
+                    if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+                {
+                    if idle_since.elapsed() >= idle_timeout {
+                        trace!("Closing idle persistent connection");
+                        return None;
+                    }
+                }
+                Err(err) => {
+                    format_error!("Failed to wait for the next request", err);
+                    return None;
+                }
             }
-            Err(err) => format_error!("Failed to send response", err),
+        }
+    }
+}
+
+/// Result of a successful authentication kept for the lifetime of a persistent connection.
+///
+/// Only authenticators whose result is tied to the connection metadata are cached: a later
+/// request on the same connection, with the same authentication type and payload, gets the same
+/// application without being authenticated again.
+struct CachedAuthentication {
+    auth_type: AuthType,
+    auth: Zeroizing<Vec<u8>>,
+    app: Application,
+}
+
+impl CachedAuthentication {
+    fn new(auth_type: AuthType, auth: &RequestAuth, app: Application) -> Self {
+        CachedAuthentication {
+            auth_type,
+            auth: Zeroizing::new(auth.buffer.expose_secret().clone()),
+            app,
+        }
+    }
+
+    fn get(&self, auth_type: AuthType, auth: &RequestAuth) -> Option<Application> {
+        if self.auth_type == auth_type && *self.auth == *auth.buffer.expose_secret() {
+            Some(self.app.clone())
+        } else {
+            None
         }
     }
 }
@@ -129,8 +477,13 @@ impl FrontEndHandler {
 pub struct FrontEndHandlerBuilder {
     dispatcher: Option<Dispatcher>,
     #[derivative(Debug = "ignore")]
-    authenticators: Option<HashMap<AuthType, Box<dyn Authenticate + Send + Sync>>>,
+    authenticators: Option<HashMap<AuthType, Arc<dyn Authenticate + Send + Sync>>>,
     body_len_limit: Option<usize>,
+    max_requests_per_connection: Option<usize>,
+    connection_idle_timeout: Option<Duration>,
+    rejected_connections: Option<Arc<AtomicUsize>>,
+    rate_limiter: Option<RateLimiter>,
+    recorder: Option<Recorder>,
 }
 
 impl FrontEndHandlerBuilder {
@@ -140,6 +493,11 @@ impl FrontEndHandlerBuilder {
             dispatcher: None,
             authenticators: None,
             body_len_limit: None,
+            max_requests_per_connection: None,
+            connection_idle_timeout: None,
+            rejected_connections: None,
+            rate_limiter: None,
+            recorder: None,
         }
     }
 
@@ -153,7 +511,7 @@ impl FrontEndHandlerBuilder {
     pub fn with_authenticator(
         mut self,
         auth_type: AuthType,
-        authenticator: Box<dyn Authenticate + Send + Sync>,
+        authenticator: Arc<dyn Authenticate + Send + Sync>,
     ) -> Self {
         match &mut self.authenticators {
             Some(authenticators) => {
@@ -175,6 +533,37 @@ impl FrontEndHandlerBuilder {
         self
     }
 
+    /// Keep connections open to serve several requests, up to `max_requests` per connection.
+    /// Connections are closed after staying idle for `idle_timeout`.
+    pub fn with_persistent_connections(
+        mut self,
+        idle_timeout: Duration,
+        max_requests: usize,
+    ) -> Self {
+        self.connection_idle_timeout = Some(idle_timeout);
+        self.max_requests_per_connection = Some(max_requests);
+        self
+    }
+
+    /// Count the rejected connections with `rejected_connections`, so that the count is kept when
+    /// the front end handler is replaced.
+    pub fn with_rejected_connections(mut self, rejected_connections: Arc<AtomicUsize>) -> Self {
+        self.rejected_connections = Some(rejected_connections);
+        self
+    }
+
+    /// Limit the rate of requests of each application
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Record the requests and responses into a capture file
+    pub fn with_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
     /// Build into a FrontEndHandler
     pub fn build(self) -> Result<FrontEndHandler> {
         Ok(FrontEndHandler {
@@ -187,6 +576,225 @@ impl FrontEndHandlerBuilder {
             body_len_limit: self
                 .body_len_limit
                 .ok_or_else(|| Error::new(ErrorKind::InvalidData, "body_len_limit is missing"))?,
+            max_requests_per_connection: self.max_requests_per_connection.unwrap_or(1),
+            connection_idle_timeout: self.connection_idle_timeout,
+            rejected_connections: self.rejected_connections.unwrap_or_default(),
+            rate_limiter: self.rate_limiter,
+            recorder: self.recorder,
         })
     }
 }
+
+#[cfg(feature = "unix-peer-credentials-authenticator")]
+#[cfg(test)]
+mod test {
+    use super::{FrontEndHandler, FrontEndHandlerBuilder};
+    use crate::authenticators::unix_peer_credentials_authenticator::UnixPeerCredentialsAuthenticator;
+    use crate::back::backend_handler::BackEndHandlerBuilder;
+    use crate::back::dispatcher::DispatcherBuilder;
+    use crate::front::listener::{Connection, Protocol};
+    use crate::providers::core::ProviderBuilder as CoreProviderBuilder;
+    use parsec_interface::operations::{ping, Convert, NativeOperation};
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+    use parsec_interface::requests::{
+        AuthType, BodyType, Opcode, ProviderId, Request, Response, ResponseStatus,
+    };
+    #[cfg(feature = "http-gateway")]
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn front_end_handler(persistent: bool) -> FrontEndHandler {
+        front_end_handler_builder(persistent).build().unwrap()
+    }
+
+    fn front_end_handler_builder(persistent: bool) -> FrontEndHandlerBuilder {
+        let core_provider = CoreProviderBuilder::new()
+            .with_wire_protocol_version(0, 1)
+            .build()
+            .unwrap();
+        let backend_handler = BackEndHandlerBuilder::new()
+            .with_provider(Arc::new(core_provider))
+            .with_converter(Box::from(ProtobufConverter {}))
+            .with_provider_id(ProviderId::Core)
+            .with_content_type(BodyType::Protobuf)
+            .with_accept_type(BodyType::Protobuf)
+            .build()
+            .unwrap();
+        let dispatcher = DispatcherBuilder::new()
+            .with_backend(ProviderId::Core, backend_handler)
+            .build()
+            .unwrap();
+        let mut builder = FrontEndHandlerBuilder::new()
+            .with_dispatcher(dispatcher)
+            .with_authenticator(
+                AuthType::UnixPeerCredentials,
+                Arc::new(UnixPeerCredentialsAuthenticator::new(Vec::new())),
+            )
+            .with_body_len_limit(1 << 20);
+        if persistent {
+            builder = builder.with_persistent_connections(Duration::from_millis(200), 3);
+        }
+        builder
+    }
+
+    fn ping_request() -> Request {
+        Request {
+            header: RequestHeader {
+                provider: ProviderId::Core,
+                session: 0,
+                content_type: BodyType::Protobuf,
+                accept_type: BodyType::Protobuf,
+                auth_type: AuthType::NoAuth,
+                opcode: Opcode::Ping,
+            },
+            body: ProtobufConverter {}
+                .operation_to_body(NativeOperation::Ping(ping::Operation {}))
+                .unwrap(),
+            auth: RequestAuth::new(Vec::new()),
+        }
+    }
+
+    /// Send `requests` pings on a connection handled by `front_end_handler` and return the
+    /// number of responses received before the connection was closed.
+    fn count_responses(front_end_handler: FrontEndHandler, requests: usize) -> usize {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let handler = thread::spawn(move || {
+            front_end_handler.handle_request(Connection {
+                stream: Box::new(server),
+                metadata: None,
+                permitted_authenticators: None,
+                protocol: Protocol::Parsec,
+                handshake: None,
+            })
+        });
+
+        for _ in 0..requests {
+            ping_request().write_to_stream(&mut client).unwrap();
+        }
+        let mut responses = 0;
+        while let Ok(response) = Response::read_from_stream(&mut client, 1 << 20) {
+            assert_eq!(response.header.status, ResponseStatus::Success);
+            responses += 1;
+        }
+        handler.join().unwrap();
+        responses
+    }
+
+    #[test]
+    fn authenticator_not_permitted_on_connection() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let handler = thread::spawn(move || {
+            front_end_handler(true).handle_request(Connection {
+                stream: Box::new(server),
+                metadata: None,
+                permitted_authenticators: Some(vec![AuthType::Direct]),
+                protocol: Protocol::Parsec,
+                handshake: None,
+            })
+        });
+
+        let mut request = ping_request();
+        request.header.auth_type = AuthType::UnixPeerCredentials;
+        request.auth = RequestAuth::new(vec![0; 4]);
+        request.write_to_stream(&mut client).unwrap();
+        let response = Response::read_from_stream(&mut client, 1 << 20).unwrap();
+        assert_eq!(
+            response.header.status,
+            ResponseStatus::AuthenticatorNotRegistered
+        );
+
+        // Requests without authentication are still served.
+        ping_request().write_to_stream(&mut client).unwrap();
+        let response = Response::read_from_stream(&mut client, 1 << 20).unwrap();
+        assert_eq!(response.header.status, ResponseStatus::Success);
+
+        drop(client);
+        handler.join().unwrap();
+    }
+
+    #[test]
+    fn rejected_connection_gets_busy_response() {
+        let front_end_handler = front_end_handler(false);
+        let (mut client, server) = UnixStream::pair().unwrap();
+        ping_request().write_to_stream(&mut client).unwrap();
+
+        front_end_handler.reject_connection(Connection {
+            stream: Box::new(server),
+            metadata: None,
+            permitted_authenticators: None,
+            protocol: Protocol::Parsec,
+            handshake: None,
+        });
+
+        let response = Response::read_from_stream(&mut client, 1 << 20).unwrap();
+        assert_eq!(
+            response.header.status,
+            ResponseStatus::PsaErrorInsufficientMemory
+        );
+        assert_eq!(front_end_handler.rejected_connections(), 1);
+    }
+
+    #[test]
+    fn rejected_connections_count_survives_rebuild() {
+        let rejected_connections = Arc::new(AtomicUsize::new(0));
+        for expected in 1..=2 {
+            let front_end_handler = front_end_handler_builder(false)
+                .with_rejected_connections(rejected_connections.clone())
+                .build()
+                .unwrap();
+            let (_client, server) = UnixStream::pair().unwrap();
+            front_end_handler.reject_connection(Connection {
+                stream: Box::new(server),
+                metadata: None,
+                permitted_authenticators: None,
+                protocol: Protocol::Parsec,
+                handshake: None,
+            });
+            assert_eq!(front_end_handler.rejected_connections(), expected);
+        }
+    }
+
+    #[cfg(feature = "http-gateway")]
+    #[test]
+    fn rejected_http_connection_gets_busy_response() {
+        let front_end_handler = front_end_handler(false);
+        let (mut client, server) = UnixStream::pair().unwrap();
+
+        front_end_handler.reject_connection(Connection {
+            stream: Box::new(server),
+            metadata: None,
+            permitted_authenticators: None,
+            protocol: Protocol::Http,
+            handshake: None,
+        });
+
+        let mut response = String::new();
+        let _ = client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+        assert_eq!(front_end_handler.rejected_connections(), 1);
+    }
+
+    #[test]
+    fn one_request_per_connection_by_default() {
+        assert_eq!(count_responses(front_end_handler(false), 2), 1);
+    }
+
+    #[test]
+    fn persistent_connection_serves_several_requests() {
+        assert_eq!(count_responses(front_end_handler(true), 2), 2);
+    }
+
+    #[test]
+    fn persistent_connection_request_cap() {
+        assert_eq!(count_responses(front_end_handler(true), 5), 3);
+    }
+}
@@ -0,0 +1,941 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! HTTP gateway front end
+//!
+//! Expose Parsec operations as HTTP endpoints, for clients which can not link a Parsec client
+//! library. Each HTTP request is translated into the equivalent Parsec request, which then goes
+//! through the usual authentication, rate limiting and dispatching of the front end handler.
+//!
+//! The following endpoints are served, with JSON request and response bodies. Binary data, such
+//! as hashes and signatures, is encoded in base64.
+//!
+//! | Endpoint                        | Operation            | Request body                      |
+//! |---------------------------------|----------------------|-----------------------------------|
+//! | `GET /v1/ping`                  | Ping                 |                                   |
+//! | `GET /v1/providers`             | ListProviders        |                                   |
+//! | `GET /v1/keys`                  | ListKeys             |                                   |
+//! | `POST /v1/keys/{name}`          | PsaGenerateKey       | `attributes`                      |
+//! | `DELETE /v1/keys/{name}`        | PsaDestroyKey        |                                   |
+//! | `GET /v1/keys/{name}/public`    | PsaExportPublicKey   |                                   |
+//! | `POST /v1/keys/{name}/sign`     | PsaSignHash          | `alg`, `hash`                     |
+//! | `POST /v1/keys/{name}/verify`   | PsaVerifyHash        | `alg`, `hash`, `signature`        |
+//! | `POST /v1/random`               | PsaGenerateRandom    | `size`                            |
+//! | `POST /v1/batch`                | (several)            | `operations`                      |
+//!
+//! The provider executing the operation can be chosen with the `provider` query parameter, set to
+//! a provider ID or, to choose one of several providers of the same type, to a provider name.
+//! Otherwise, the first provider listed by the core provider is used.
+//!
+//! A batch executes several of the operations above in one round trip, authenticating the client
+//! only once. Each element of `operations` has a `method`, a `path` and, if needed, a `body`, as
+//! they would be sent on their own. The response lists the `status` and `body` of each operation,
+//! in the same order. All the operations of a batch are executed by the same provider.
+//!
+//! Requests carrying an `Authorization: Bearer` header are authenticated with the JWT authenticator
+//! if it is enabled, with the JWT-SVID authenticator otherwise. Otherwise, requests received on a
+//! Unix domain socket are authenticated with the peer credentials of the connection, and other
+//! requests are sent without authentication.
+use super::front_end::FrontEndHandler;
+use super::listener;
+use crate::utils::correlation_id::{CorrelationId, CorrelationScope};
+use anyhow::{Context, Result};
+use derivative::Derivative;
+use listener::Listen;
+use listener::{Connection, ConnectionMetadata, Protocol};
+use log::error;
+use parsec_interface::operations::psa_algorithm::AsymmetricSignature;
+use parsec_interface::operations::psa_key_attributes::Attributes;
+use parsec_interface::operations::{
+    list_keys, list_providers, ping, psa_destroy_key, psa_export_public_key, psa_generate_key,
+    psa_generate_random, psa_sign_hash, psa_verify_hash,
+};
+use parsec_interface::operations::{Convert, NativeOperation, NativeResult};
+use parsec_interface::operations_protobuf::ProtobufConverter;
+use parsec_interface::requests::request::{RequestAuth, RequestHeader};
+use parsec_interface::requests::{
+    AuthType, BodyType, ProviderId, Request, Response, ResponseStatus,
+};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
+use zeroize::Zeroizing;
+
+/// Maximum size of the request line and headers of an HTTP request
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+/// Maximum number of headers of an HTTP request
+const MAX_HEADERS: usize = 32;
+
+/// Listener whose connections are served by the HTTP gateway
+///
+/// Wraps any other listener and marks its connections as using HTTP.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct HttpListener {
+    #[derivative(Debug = "ignore")]
+    listener: Box<dyn Listen>,
+}
+
+impl HttpListener {
+    /// Serve the connections accepted by `listener` with the HTTP gateway.
+    pub fn new(listener: Box<dyn Listen>) -> Self {
+        HttpListener { listener }
+    }
+}
+
+impl Listen for HttpListener {
+    fn set_timeout(&mut self, duration: Duration) {
+        self.listener.set_timeout(duration);
+    }
+
+    fn accept(&self) -> Option<Connection> {
+        let mut connection = self.listener.accept()?;
+        connection.protocol = Protocol::Http;
+        Some(connection)
+    }
+}
+
+impl AsRawFd for HttpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+/// Plain TCP IPC manager
+///
+/// Listener implementation for unprotected TCP connections, which carry no connection metadata.
+/// It is only meant to serve the HTTP gateway on a local address.
+#[derive(Debug)]
+pub struct PlainTcpListener {
+    listener: TcpListener,
+    timeout: Duration,
+}
+
+impl PlainTcpListener {
+    /// Bind to the TCP address given.
+    pub fn new(timeout: Duration, address: String) -> Result<Self> {
+        let listener = TcpListener::bind(&address)
+            .with_context(|| format!("Failed to bind to TCP address {}", address))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self { listener, timeout })
+    }
+}
+
+impl Listen for PlainTcpListener {
+    fn set_timeout(&mut self, duration: Duration) {
+        self.timeout = duration;
+    }
+
+    fn accept(&self) -> Option<Connection> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                // Check if the error is because no connections are currently present.
+                if err.kind() != ErrorKind::WouldBlock {
+                    // Only log the real errors.
+                    format_error!("Failed to connect with a TcpStream", err);
+                }
+                return None;
+            }
+        };
+
+        if let Err(err) = configure_stream(&stream, self.timeout) {
+            format_error!("Failed to configure the TCP stream", err);
+            return None;
+        }
+
+        Some(Connection {
+            stream: Box::new(stream),
+            metadata: None,
+            permitted_authenticators: None,
+            protocol: Protocol::Parsec,
+            handshake: None,
+        })
+    }
+}
+
+impl AsRawFd for PlainTcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+fn configure_stream(stream: &TcpStream, timeout: Duration) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.set_nonblocking(false)
+}
+
+/// Builder for `PlainTcpListener`
+#[derive(Clone, Debug, Default)]
+pub struct PlainTcpListenerBuilder {
+    timeout: Option<Duration>,
+    address: Option<String>,
+}
+
+impl PlainTcpListenerBuilder {
+    /// Create a new PlainTcpListener builder
+    pub fn new() -> Self {
+        PlainTcpListenerBuilder {
+            timeout: None,
+            address: None,
+        }
+    }
+
+    /// Add a timeout on the TCP connections
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Specify the TCP address to listen on
+    pub fn with_address(mut self, address: Option<String>) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Build the builder into the listener
+    pub fn build(self) -> Result<PlainTcpListener> {
+        let timeout = self.timeout.ok_or_else(|| {
+            error!("The listener timeout was not set.");
+            Error::new(ErrorKind::InvalidInput, "listener timeout missing")
+        })?;
+        let address = self.address.ok_or_else(|| {
+            error!("The TCP address to listen on was not set.");
+            Error::new(ErrorKind::InvalidInput, "listener address missing")
+        })?;
+
+        PlainTcpListener::new(timeout, address)
+    }
+}
+
+/// Read one HTTP request from the connection, execute it and write the HTTP response back.
+///
+/// The connection is closed after the response: keep-alive is not supported. The correlation ID
+/// given to the request is sent back in the `X-Correlation-Id` header of the response.
+pub fn handle_connection(front_end_handler: &FrontEndHandler, mut connection: Connection) {
+    let _correlation_scope = CorrelationScope::enter(CorrelationId::new());
+    let response = match HttpRequest::read_from_stream(
+        &mut connection.stream,
+        front_end_handler.body_len_limit(),
+    ) {
+        Ok(request) => handle_http_request(front_end_handler, &connection, request)
+            .unwrap_or_else(|response| response),
+        Err(response) => response,
+    };
+
+    if let Err(err) = response.write_to_stream(&mut connection.stream) {
+        format_error!("Failed to send the HTTP response", err);
+    }
+}
+
+/// Reject a connection because the service is overloaded, with a `503 Service Unavailable`
+/// response sent without reading the request.
+pub fn reject_connection(mut connection: Connection) {
+    let response = HttpResponse::from_status(ResponseStatus::PsaErrorInsufficientMemory);
+    if let Err(err) = response.write_to_stream(&mut connection.stream) {
+        format_error!("Failed to send the busy HTTP response", err);
+    }
+}
+
+/// Parts of an HTTP request used by the gateway
+#[derive(Debug)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Option<String>,
+    authorization: Option<Zeroizing<String>>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn read_from_stream(
+        stream: &mut impl Read,
+        body_len_limit: usize,
+    ) -> std::result::Result<Self, HttpResponse> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 1024];
+
+        let (mut request, headers_len, body_len) = loop {
+            let len = stream.read(&mut chunk).map_err(|err| {
+                format_error!("Failed to read the HTTP request", err);
+                HttpResponse::error(400, "failed to read the request")
+            })?;
+            if len == 0 {
+                return Err(HttpResponse::error(400, "incomplete request"));
+            }
+            buffer.extend_from_slice(&chunk[..len]);
+
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut parsed = httparse::Request::new(&mut headers);
+            match parsed.parse(&buffer) {
+                Ok(httparse::Status::Complete(headers_len)) => {
+                    break Self::from_parsed(&parsed, headers_len)?
+                }
+                Ok(httparse::Status::Partial) if buffer.len() > MAX_HEADERS_SIZE => {
+                    return Err(HttpResponse::error(431, "request headers are too large"));
+                }
+                Ok(httparse::Status::Partial) => continue,
+                Err(err) => {
+                    format_error!("Failed to parse the HTTP request", err);
+                    return Err(HttpResponse::error(400, "malformed request"));
+                }
+            }
+        };
+
+        if body_len > body_len_limit {
+            return Err(HttpResponse::error(413, "request body is too large"));
+        }
+        let _ = buffer.drain(..headers_len);
+        buffer.truncate(body_len);
+        let already_read = buffer.len();
+        buffer.resize(body_len, 0);
+        stream
+            .read_exact(&mut buffer[already_read..])
+            .map_err(|err| {
+                format_error!("Failed to read the HTTP request body", err);
+                HttpResponse::error(400, "failed to read the request body")
+            })?;
+        request.body = buffer;
+
+        Ok(request)
+    }
+
+    /// Extract the request from its parsed headers. The body is left empty.
+    ///
+    /// Returns the request, the length of its headers and the length of its body.
+    fn from_parsed(
+        parsed: &httparse::Request,
+        headers_len: usize,
+    ) -> std::result::Result<(Self, usize, usize), HttpResponse> {
+        let target = parsed.path.unwrap_or("/");
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+
+        let mut authorization = None;
+        let mut body_len = 0;
+        for header in parsed.headers.iter() {
+            if header.name.eq_ignore_ascii_case("Authorization") {
+                authorization = Some(Zeroizing::new(
+                    String::from_utf8_lossy(header.value).into_owned(),
+                ));
+            } else if header.name.eq_ignore_ascii_case("Content-Length") {
+                body_len = std::str::from_utf8(header.value)
+                    .ok()
+                    .and_then(|value| value.trim().parse().ok())
+                    .ok_or_else(|| HttpResponse::error(400, "invalid Content-Length header"))?;
+            } else if header.name.eq_ignore_ascii_case("Transfer-Encoding") {
+                return Err(HttpResponse::error(
+                    411,
+                    "a Content-Length header is required",
+                ));
+            }
+        }
+
+        let request = HttpRequest {
+            method: parsed.method.unwrap_or_default().to_string(),
+            path: path.to_string(),
+            query,
+            authorization,
+            body: Vec::new(),
+        };
+        Ok((request, headers_len, body_len))
+    }
+
+    /// Value of a query parameter
+    fn query_parameter(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .find_map(|parameter| match parameter.split_once('=') {
+                Some((key, value)) if key == name => Some(value),
+                _ => None,
+            })
+    }
+
+    /// JSON body of the request, `null` if it is empty
+    fn json_body(&self) -> std::result::Result<Value, HttpResponse> {
+        if self.body.is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_slice(&self.body).map_err(|err| {
+            format_error!("Failed to deserialize the HTTP request body", err);
+            HttpResponse::error(400, &format!("invalid request body: {}", err))
+        })
+    }
+}
+
+/// HTTP response with a JSON body
+#[derive(Debug)]
+struct HttpResponse {
+    status: u16,
+    body: Value,
+}
+
+impl HttpResponse {
+    fn ok(body: Value) -> Self {
+        HttpResponse { status: 200, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        HttpResponse {
+            status,
+            body: json!({ "error": message }),
+        }
+    }
+
+    /// Response to a Parsec request which failed with the given status
+    fn from_status(status: ResponseStatus) -> Self {
+        let http_status = match status {
+            ResponseStatus::NotAuthenticated
+            | ResponseStatus::AuthenticationError
+            | ResponseStatus::AuthenticatorDoesNotExist
+            | ResponseStatus::AuthenticatorNotRegistered => 401,
+            ResponseStatus::AdminOperation | ResponseStatus::PsaErrorNotPermitted => 403,
+            ResponseStatus::PsaErrorDoesNotExist
+            | ResponseStatus::ProviderDoesNotExist
+            | ResponseStatus::ProviderNotRegistered => 404,
+            ResponseStatus::PsaErrorAlreadyExists => 409,
+            ResponseStatus::PsaErrorInvalidArgument
+            | ResponseStatus::PsaErrorInvalidSignature
+            | ResponseStatus::DeserializingBodyFailed => 400,
+            ResponseStatus::PsaErrorNotSupported => 501,
+            ResponseStatus::PsaErrorInsufficientMemory
+            | ResponseStatus::PsaErrorCommunicationFailure => 503,
+            _ => 500,
+        };
+        HttpResponse {
+            status: http_status,
+            body: json!({
+                "error": status.to_string(),
+                "status": format!("{:?}", status),
+                "code": status as u16,
+            }),
+        }
+    }
+
+    fn write_to_stream(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        let body = self.body.to_string();
+        write!(
+            stream,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            self.status,
+            reason_phrase(self.status),
+            body.len(),
+        )?;
+        if let Some(correlation_id) = CorrelationId::current() {
+            write!(stream, "X-Correlation-Id: {}\r\n", correlation_id)?;
+        }
+        write!(stream, "Connection: close\r\n\r\n{}", body)?;
+        stream.flush()
+    }
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[derive(Deserialize)]
+struct GenerateKeyBody {
+    attributes: Attributes,
+}
+
+#[derive(Deserialize)]
+struct SignHashBody {
+    alg: AsymmetricSignature,
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct VerifyHashBody {
+    alg: AsymmetricSignature,
+    hash: String,
+    signature: String,
+}
+
+#[derive(Deserialize)]
+struct GenerateRandomBody {
+    size: usize,
+}
+
+#[derive(Deserialize)]
+struct BatchBody {
+    operations: Vec<BatchOperation>,
+}
+
+#[derive(Deserialize)]
+struct BatchOperation {
+    method: String,
+    path: String,
+    #[serde(default)]
+    body: Value,
+}
+
+/// Deserialize the JSON body of an operation
+fn deserialize_body<T: DeserializeOwned>(body: Value) -> std::result::Result<T, HttpResponse> {
+    T::deserialize(body).map_err(|err| {
+        format_error!("Failed to deserialize the HTTP request body", err);
+        HttpResponse::error(400, &format!("invalid request body: {}", err))
+    })
+}
+
+fn decode_base64(field: &str, value: &str) -> std::result::Result<Vec<u8>, HttpResponse> {
+    base64::decode(value)
+        .map_err(|_| HttpResponse::error(400, &format!("{} is not valid base64", field)))
+}
+
+/// Decode the percent-encoded characters of a path segment.
+fn percent_decode(segment: &str) -> std::result::Result<String, HttpResponse> {
+    let invalid = || HttpResponse::error(400, "invalid percent-encoding in the path");
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = bytes.get(index + 1..index + 3).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| invalid())
+}
+
+/// Translate the HTTP request into Parsec operations, execute them and translate the results back.
+fn handle_http_request(
+    front_end_handler: &FrontEndHandler,
+    connection: &Connection,
+    request: HttpRequest,
+) -> std::result::Result<HttpResponse, HttpResponse> {
+    let body = request.json_body()?;
+    let (auth_type, auth) = authentication(front_end_handler, &request, connection);
+    let provider_name = match request.query_parameter("provider") {
+        Some(provider) if provider.parse::<u8>().is_err() => Some(percent_decode(provider)?),
+        _ => None,
+    };
+    // The provider is only looked up once, the first time a non-core operation needs it.
+    let mut provider = None;
+    let mut provider_for = |operation: &NativeOperation| {
+        if operation.opcode().is_core() {
+            return Ok(ProviderId::Core);
+        }
+        if let Some(provider) = provider {
+            return Ok(provider);
+        }
+        let provider_id = match request.query_parameter("provider") {
+            // The request is addressed to the named provider by the dispatcher.
+            Some(_) if provider_name.is_some() => ProviderId::Core,
+            Some(provider) => provider
+                .parse()
+                .ok()
+                .and_then(num_traits::FromPrimitive::from_u8)
+                .ok_or_else(|| HttpResponse::error(400, "invalid provider ID"))?,
+            None => default_provider(front_end_handler, connection)?,
+        };
+        provider = Some(provider_id);
+        Ok(provider_id)
+    };
+
+    if request.method == "POST" && request.path.trim_matches('/') == "v1/batch" {
+        let batch: BatchBody = deserialize_body(body)?;
+        let mut results = Vec::with_capacity(batch.operations.len());
+        let mut requests = Vec::with_capacity(batch.operations.len());
+        for operation in batch.operations {
+            let request = parse_operation(&operation.method, &operation.path, operation.body)
+                .and_then(|operation| {
+                    let provider = provider_for(&operation)?;
+                    to_request(operation, provider, auth_type, auth.clone())
+                });
+            match request {
+                Ok(request) => {
+                    results.push(None);
+                    requests.push(request);
+                }
+                Err(response) => results.push(Some(response)),
+            }
+        }
+
+        let mut responses = front_end_handler
+            .process_batch(requests, provider_name.as_deref(), connection)
+            .into_iter();
+        let results = results
+            .into_iter()
+            .map(|result| {
+                let response =
+                    result.unwrap_or_else(|| match responses.next().map(from_response) {
+                        Some(Ok(result)) => HttpResponse::ok(result_to_json(result)),
+                        Some(Err(response)) => response,
+                        None => HttpResponse::from_status(ResponseStatus::PsaErrorGenericError),
+                    });
+                json!({ "status": response.status, "body": response.body })
+            })
+            .collect::<Vec<Value>>();
+        return Ok(HttpResponse::ok(json!({ "results": results })));
+    }
+
+    let operation = parse_operation(&request.method, &request.path, body)?;
+    let provider = provider_for(&operation)?;
+    let request = to_request(operation, provider, auth_type, auth)?;
+    let result = from_response(front_end_handler.process_request(
+        request,
+        provider_name.as_deref(),
+        connection,
+    ))?;
+
+    Ok(HttpResponse::ok(result_to_json(result)))
+}
+
+/// Translate an HTTP method, path and JSON body into a Parsec operation.
+fn parse_operation(
+    method: &str,
+    path: &str,
+    body: Value,
+) -> std::result::Result<NativeOperation, HttpResponse> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let key_name = match segments.get(2) {
+        Some(key_name) => percent_decode(key_name)?,
+        None => String::new(),
+    };
+
+    let operation = match (method, segments.as_slice()) {
+        ("GET", ["v1", "ping"]) => NativeOperation::Ping(ping::Operation {}),
+        ("GET", ["v1", "providers"]) => {
+            NativeOperation::ListProviders(list_providers::Operation {})
+        }
+        ("GET", ["v1", "keys"]) => NativeOperation::ListKeys(list_keys::Operation {}),
+        ("POST", ["v1", "keys", _]) => {
+            let body: GenerateKeyBody = deserialize_body(body)?;
+            NativeOperation::PsaGenerateKey(psa_generate_key::Operation {
+                key_name,
+                attributes: body.attributes,
+            })
+        }
+        ("DELETE", ["v1", "keys", _]) => {
+            NativeOperation::PsaDestroyKey(psa_destroy_key::Operation { key_name })
+        }
+        ("GET", ["v1", "keys", _, "public"]) => {
+            NativeOperation::PsaExportPublicKey(psa_export_public_key::Operation { key_name })
+        }
+        ("POST", ["v1", "keys", _, "sign"]) => {
+            let body: SignHashBody = deserialize_body(body)?;
+            NativeOperation::PsaSignHash(psa_sign_hash::Operation {
+                key_name,
+                alg: body.alg,
+                hash: decode_base64("hash", &body.hash)?.into(),
+            })
+        }
+        ("POST", ["v1", "keys", _, "verify"]) => {
+            let body: VerifyHashBody = deserialize_body(body)?;
+            NativeOperation::PsaVerifyHash(psa_verify_hash::Operation {
+                key_name,
+                alg: body.alg,
+                hash: decode_base64("hash", &body.hash)?.into(),
+                signature: decode_base64("signature", &body.signature)?.into(),
+            })
+        }
+        ("POST", ["v1", "random"]) => {
+            let body: GenerateRandomBody = deserialize_body(body)?;
+            NativeOperation::PsaGenerateRandom(psa_generate_random::Operation { size: body.size })
+        }
+        (_, ["v1", "ping"])
+        | (_, ["v1", "providers"])
+        | (_, ["v1", "keys"])
+        | (_, ["v1", "keys", _])
+        | (_, ["v1", "keys", _, "public"])
+        | (_, ["v1", "keys", _, "sign"])
+        | (_, ["v1", "keys", _, "verify"])
+        | (_, ["v1", "random"])
+        | (_, ["v1", "batch"]) => return Err(HttpResponse::error(405, "method not allowed")),
+        _ => return Err(HttpResponse::error(404, "unknown endpoint")),
+    };
+
+    Ok(operation)
+}
+
+/// Authentication of the request, based on its headers and on the connection it came from
+fn authentication(
+    front_end_handler: &FrontEndHandler,
+    request: &HttpRequest,
+    connection: &Connection,
+) -> (AuthType, Vec<u8>) {
+    if let Some(token) = request
+        .authorization
+        .as_ref()
+        .and_then(|authorization| authorization.strip_prefix("Bearer "))
+    {
+        let auth_type = if front_end_handler.has_authenticator(AuthType::Jwt) {
+            AuthType::Jwt
+        } else {
+            AuthType::JwtSvid
+        };
+        (auth_type, token.trim().as_bytes().to_vec())
+    } else if let Some(ConnectionMetadata::UnixPeerCredentials { uid, .. }) = connection.metadata {
+        (AuthType::UnixPeerCredentials, uid.to_le_bytes().to_vec())
+    } else {
+        (AuthType::NoAuth, Vec::new())
+    }
+}
+
+/// First provider listed by the core provider, as chosen by default by the client libraries.
+fn default_provider(
+    front_end_handler: &FrontEndHandler,
+    connection: &Connection,
+) -> std::result::Result<ProviderId, HttpResponse> {
+    match execute(
+        front_end_handler,
+        connection,
+        NativeOperation::ListProviders(list_providers::Operation {}),
+        ProviderId::Core,
+        AuthType::NoAuth,
+        Vec::new(),
+    )? {
+        NativeResult::ListProviders(result) => result
+            .providers
+            .iter()
+            .map(|provider| provider.id)
+            .find(|id| *id != ProviderId::Core)
+            .ok_or_else(|| HttpResponse::from_status(ResponseStatus::ProviderNotRegistered)),
+        _ => Err(HttpResponse::from_status(
+            ResponseStatus::PsaErrorGenericError,
+        )),
+    }
+}
+
+/// Send the operation through the front end handler and return its result.
+fn execute(
+    front_end_handler: &FrontEndHandler,
+    connection: &Connection,
+    operation: NativeOperation,
+    provider: ProviderId,
+    auth_type: AuthType,
+    auth: Vec<u8>,
+) -> std::result::Result<NativeResult, HttpResponse> {
+    let request = to_request(operation, provider, auth_type, auth)?;
+    from_response(front_end_handler.process_request(request, None, connection))
+}
+
+/// Build the Parsec request of an operation.
+fn to_request(
+    operation: NativeOperation,
+    provider: ProviderId,
+    auth_type: AuthType,
+    auth: Vec<u8>,
+) -> std::result::Result<Request, HttpResponse> {
+    Ok(Request {
+        header: RequestHeader {
+            provider,
+            session: 0,
+            content_type: BodyType::Protobuf,
+            accept_type: BodyType::Protobuf,
+            auth_type,
+            opcode: operation.opcode(),
+        },
+        body: ProtobufConverter {}
+            .operation_to_body(operation)
+            .map_err(HttpResponse::from_status)?,
+        auth: RequestAuth::new(auth),
+    })
+}
+
+/// Extract the result of an operation from its Parsec response.
+fn from_response(response: Response) -> std::result::Result<NativeResult, HttpResponse> {
+    if response.header.status != ResponseStatus::Success {
+        return Err(HttpResponse::from_status(response.header.status));
+    }
+    ProtobufConverter {}
+        .body_to_result(response.body, response.header.opcode)
+        .map_err(HttpResponse::from_status)
+}
+
+fn result_to_json(result: NativeResult) -> Value {
+    match result {
+        NativeResult::Ping(result) => json!({
+            "wire_protocol_version": format!(
+                "{}.{}",
+                result.wire_protocol_version_maj, result.wire_protocol_version_min
+            ),
+        }),
+        NativeResult::ListProviders(result) => json!({
+            "providers": result
+                .providers
+                .iter()
+                .map(|provider| json!({
+                    "id": provider.id as u8,
+                    "uuid": provider.uuid.to_string(),
+                    "description": provider.description,
+                    "vendor": provider.vendor,
+                    "version": format!(
+                        "{}.{}.{}",
+                        provider.version_maj, provider.version_min, provider.version_rev
+                    ),
+                }))
+                .collect::<Vec<Value>>(),
+        }),
+        NativeResult::ListKeys(result) => json!({
+            "keys": result
+                .keys
+                .iter()
+                .map(|key| json!({
+                    "name": key.name,
+                    "provider_id": key.provider_id as u8,
+                    "attributes": serde_json::to_value(key.attributes).unwrap_or(Value::Null),
+                }))
+                .collect::<Vec<Value>>(),
+        }),
+        NativeResult::PsaExportPublicKey(result) => json!({
+            "data": base64::encode(&*result.data),
+        }),
+        NativeResult::PsaSignHash(result) => json!({
+            "signature": base64::encode(&*result.signature),
+        }),
+        NativeResult::PsaGenerateRandom(result) => json!({
+            "random_bytes": base64::encode(&*result.random_bytes),
+        }),
+        // The other operations sent by the gateway do not return any data.
+        _ => json!({}),
+    }
+}
+
+#[cfg(feature = "unix-peer-credentials-authenticator")]
+#[cfg(test)]
+mod test {
+    use super::handle_connection;
+    use crate::authenticators::unix_peer_credentials_authenticator::UnixPeerCredentialsAuthenticator;
+    use crate::back::backend_handler::BackEndHandlerBuilder;
+    use crate::back::dispatcher::DispatcherBuilder;
+    use crate::front::front_end::{FrontEndHandler, FrontEndHandlerBuilder};
+    use crate::front::listener::{Connection, Protocol};
+    use crate::providers::core::ProviderBuilder as CoreProviderBuilder;
+    use parsec_interface::operations_protobuf::ProtobufConverter;
+    use parsec_interface::requests::{AuthType, BodyType, ProviderId};
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::Arc;
+
+    fn front_end_handler() -> FrontEndHandler {
+        let core_provider = CoreProviderBuilder::new()
+            .with_wire_protocol_version(0, 1)
+            .build()
+            .unwrap();
+        let backend_handler = BackEndHandlerBuilder::new()
+            .with_provider(Arc::new(core_provider))
+            .with_converter(Box::from(ProtobufConverter {}))
+            .with_provider_id(ProviderId::Core)
+            .with_content_type(BodyType::Protobuf)
+            .with_accept_type(BodyType::Protobuf)
+            .build()
+            .unwrap();
+        let dispatcher = DispatcherBuilder::new()
+            .with_backend(ProviderId::Core, backend_handler)
+            .build()
+            .unwrap();
+        FrontEndHandlerBuilder::new()
+            .with_dispatcher(dispatcher)
+            .with_authenticator(
+                AuthType::UnixPeerCredentials,
+                Arc::new(UnixPeerCredentialsAuthenticator::new(Vec::new())),
+            )
+            .with_body_len_limit(1 << 20)
+            .build()
+            .unwrap()
+    }
+
+    /// Send the raw HTTP request to the gateway and return the raw response.
+    fn exchange(request: &str) -> String {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        handle_connection(
+            &front_end_handler(),
+            Connection {
+                stream: Box::new(server),
+                metadata: None,
+                permitted_authenticators: None,
+                protocol: Protocol::Http,
+                handshake: None,
+            },
+        );
+
+        let mut response = String::new();
+        let _ = client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn ping() {
+        let response = exchange("GET /v1/ping HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with(r#"{"wire_protocol_version":"1.0"}"#));
+    }
+
+    #[test]
+    fn unknown_endpoint_and_method() {
+        let response = exchange("GET /v1/nothing HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let response = exchange("PUT /v1/ping HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn invalid_body() {
+        let response =
+            exchange("POST /v1/random?provider=1 HTTP/1.1\r\nContent-Length: 9\r\n\r\n{\"size\":}");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn provider_not_registered() {
+        let response = exchange(
+            "POST /v1/random?provider=1 HTTP/1.1\r\nContent-Length: 11\r\n\r\n{\"size\":16}",
+        );
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.contains(r#""status":"ProviderNotRegistered""#));
+    }
+
+    #[test]
+    fn batch() {
+        let body = r#"{"operations":[{"method":"GET","path":"/v1/ping"},{"method":"GET","path":"/v1/nothing"},{"method":"GET","path":"/v1/ping"}]}"#;
+        let response = exchange(&format!(
+            "POST /v1/batch HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let results = response.split("\r\n\r\n").nth(1).unwrap();
+        let results: serde_json::Value = serde_json::from_str(results).unwrap();
+        let statuses: Vec<u64> = results["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_u64().unwrap())
+            .collect();
+        assert_eq!(statuses, vec![200, 404, 200]);
+        assert_eq!(
+            results["results"][2]["body"]["wire_protocol_version"],
+            "1.0"
+        );
+    }
+}
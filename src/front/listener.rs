@@ -6,6 +6,9 @@
 //! trait acts as an interface for the operations that must be supported by any implementation
 //! of the IPC mechanism used as a Parsec front.
 use derivative::Derivative;
+use parsec_interface::requests::AuthType;
+use serde::Deserialize;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::time::Duration;
 
 /// This trait is created to allow the iterator returned by incoming to iterate over a trait object
@@ -15,7 +18,7 @@ pub trait ReadWrite: std::io::Read + std::io::Write {}
 impl<T: std::io::Read + std::io::Write> ReadWrite for T {}
 
 /// Specifies metadata associated with a connection, if any.
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum ConnectionMetadata {
     /// Unix peer credentials metadata for Unix domain sockets.
     UnixPeerCredentials {
@@ -26,10 +29,21 @@ pub enum ConnectionMetadata {
         /// The optional PID of the connecting process. This is an Option<u32> because not all
         /// platforms support retrieving PID via a domain socket.
         pid: Option<i32>,
+        /// The start time of the connecting process, used to detect that its PID was reused after
+        /// it exited. Only known if the PID is.
+        start_time: Option<u64>,
+    },
+    /// Identity of a client authenticated with a certificate during a TLS handshake.
+    TlsClientCertificate {
+        /// The subject common name of the verified client certificate, or its whole subject
+        /// distinguished name if it does not have a common name.
+        identity: String,
+    },
+    /// Address of a virtual machine guest connected through an AF_VSOCK socket.
+    VsockPeer {
+        /// The context identifier (CID) of the connecting guest.
+        cid: u32,
     },
-    // NOTE: there is currently only _one_ variant of the ConnectionMetadata enum. When a second
-    //       variant is added, you will need to update some tests!
-    //       You should grep the tests for `TODO(new_metadata_variant)` and update them accordingly.
 }
 
 /// Represents a connection to a single client
@@ -41,13 +55,55 @@ pub struct Connection {
     pub stream: Box<dyn ReadWrite + Send>,
     /// Metadata associated with the connection that might be useful elsewhere (i.e. authentication, etc)
     pub metadata: Option<ConnectionMetadata>,
+    /// Authentication types that requests received on the connection are allowed to use. All the
+    /// registered authenticators are permitted if `None`. Requests without authentication are
+    /// always permitted.
+    pub permitted_authenticators: Option<Vec<AuthType>>,
+    /// Protocol spoken by the client on the connection
+    pub protocol: Protocol,
+    /// Handshake to complete before reading requests, returning the metadata of the connection or
+    /// `None` if it failed. It can block on the client, so it is left to the thread serving the
+    /// connection rather than done when the connection is accepted.
+    #[derivative(Debug = "ignore")]
+    pub handshake: Option<Handshake>,
+}
+
+/// Deferred handshake of a connection
+pub type Handshake = Box<dyn FnOnce() -> Option<ConnectionMetadata> + Send>;
+
+impl Connection {
+    /// Complete the handshake of the connection, if it has one, and set the metadata it returns.
+    ///
+    /// Returns `false` if the handshake failed, in which case the connection must be dropped.
+    pub fn complete_handshake(&mut self) -> bool {
+        match self.handshake.take().map(|handshake| handshake()) {
+            Some(Some(metadata)) => {
+                self.metadata = Some(metadata);
+                true
+            }
+            Some(None) => false,
+            None => true,
+        }
+    }
+}
+
+/// Protocol used by the clients of a listener
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// Parsec wire protocol
+    Parsec,
+    /// HTTP requests handled by the HTTP gateway
+    Http,
 }
 
 /// IPC front manager interface
 ///
 /// Interface defining the functionality that any IPC front manager has to expose to Parsec for normal
 /// operation.
-pub trait Listen {
+///
+/// The raw file descriptor exposed by implementations must become readable when a connection is
+/// ready to be accepted. It is used to wait for incoming connections without busy-looping.
+pub trait Listen: AsRawFd {
     /// Set the timeout on read and write calls on any stream returned by this listener.
     fn set_timeout(&mut self, duration: Duration);
 
@@ -65,3 +121,43 @@ pub trait Listen {
     /// If the listener has not been initialised before, with the `init` method.
     fn accept(&self) -> Option<Connection>;
 }
+
+/// Listener restricting the authenticators that can be used on the connections it accepts
+///
+/// Wraps any other listener and marks its connections with the list of permitted authentication
+/// types, which the front end handler enforces.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct RestrictedListener {
+    #[derivative(Debug = "ignore")]
+    listener: Box<dyn Listen>,
+    permitted_authenticators: Vec<AuthType>,
+}
+
+impl RestrictedListener {
+    /// Restrict the connections accepted by `listener` to the authentication types given.
+    pub fn new(listener: Box<dyn Listen>, permitted_authenticators: Vec<AuthType>) -> Self {
+        RestrictedListener {
+            listener,
+            permitted_authenticators,
+        }
+    }
+}
+
+impl Listen for RestrictedListener {
+    fn set_timeout(&mut self, duration: Duration) {
+        self.listener.set_timeout(duration);
+    }
+
+    fn accept(&self) -> Option<Connection> {
+        let mut connection = self.listener.accept()?;
+        connection.permitted_authenticators = Some(self.permitted_authenticators.clone());
+        Some(connection)
+    }
+}
+
+impl AsRawFd for RestrictedListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
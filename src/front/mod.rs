@@ -3,4 +3,13 @@
 //! IPC front handlers
 pub mod domain_socket;
 pub mod front_end;
+#[cfg(feature = "http-gateway")]
+pub mod http_gateway;
 pub mod listener;
+pub mod poll;
+pub mod rate_limiter;
+pub mod recorder;
+#[cfg(feature = "tls-listener")]
+pub mod tcp_tls;
+#[cfg(all(feature = "vsock-listener", target_os = "linux"))]
+pub mod vsock;
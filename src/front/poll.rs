@@ -0,0 +1,139 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Readiness-based waiting on listeners
+//!
+//! Instead of repeatedly trying to accept connections and sleeping in between, the service can
+//! block in `poll(2)` until one of its listeners has a pending connection. Signals the service
+//! cares about (termination, configuration reload) are turned into wake-ups so that they are still
+//! handled straight away.
+use super::listener::Listen;
+use libc::{c_int, nfds_t, pollfd, POLLIN};
+use std::convert::TryFrom;
+use std::io::{Error, ErrorKind, Read, Result};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+/// Waits for incoming connections on listeners
+///
+/// Holds both ends of a self-pipe: signal handlers registered with `wake_on_signal` write into it,
+/// which makes any ongoing `wait` return.
+#[derive(Debug)]
+pub struct Poller {
+    wake_receiver: UnixStream,
+    wake_sender: UnixStream,
+}
+
+impl Poller {
+    /// Create a new poller.
+    pub fn new() -> Result<Self> {
+        let (wake_receiver, wake_sender) = UnixStream::pair()?;
+        wake_receiver.set_nonblocking(true)?;
+        wake_sender.set_nonblocking(true)?;
+
+        Ok(Poller {
+            wake_receiver,
+            wake_sender,
+        })
+    }
+
+    /// Interrupt the current or next call to `wait` when the given signal is received.
+    pub fn wake_on_signal(&self, signal: c_int) -> Result<()> {
+        let _ = signal_hook::low_level::pipe::register(signal, self.wake_sender.try_clone()?)?;
+        Ok(())
+    }
+
+    /// Block until at least one of the listeners has a connection ready to be accepted, until a
+    /// registered signal is received or until the timeout expires. Without timeout, the call
+    /// blocks for as long as nothing happens.
+    ///
+    /// Returns the indexes, in the `listeners` slice, of the listeners which have a connection
+    /// pending. An empty vector is returned if the wait was interrupted or timed out.
+    pub fn wait(&self, listeners: &[&dyn Listen], timeout: Option<Duration>) -> Result<Vec<usize>> {
+        let mut fds: Vec<pollfd> = listeners
+            .iter()
+            .map(|listener| pollfd {
+                fd: listener.as_raw_fd(),
+                events: POLLIN,
+                revents: 0,
+            })
+            .collect();
+        fds.push(pollfd {
+            fd: self.wake_receiver.as_raw_fd(),
+            events: POLLIN,
+            revents: 0,
+        });
+
+        let timeout = match timeout {
+            Some(timeout) => c_int::try_from(timeout.as_millis()).unwrap_or(c_int::MAX),
+            None => -1,
+        };
+
+        // Safe as the pointer and length given describe the fds vector, which lives for the whole
+        // duration of the call.
+        let ret = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as nfds_t, timeout) };
+        if ret < 0 {
+            let err = Error::last_os_error();
+            // A signal was delivered to this thread while waiting.
+            if err.kind() == ErrorKind::Interrupted {
+                return Ok(Vec::new());
+            }
+            return Err(err);
+        }
+
+        if fds[listeners.len()].revents != 0 {
+            self.drain_wake_ups();
+        }
+
+        Ok(fds[..listeners.len()]
+            .iter()
+            .enumerate()
+            .filter(|(_, fd)| fd.revents != 0)
+            .map(|(index, _)| index)
+            .collect())
+    }
+
+    fn drain_wake_ups(&self) {
+        let mut buffer = [0; 64];
+        // The receiving end is non-blocking: the loop stops once no more wake-ups are pending.
+        while let Ok(len) = (&self.wake_receiver).read(&mut buffer) {
+            if len == 0 {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Poller;
+    use crate::front::domain_socket::DomainSocketListenerBuilder;
+    use crate::front::listener::Listen;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    #[test]
+    fn wait_for_connection() {
+        let socket_path = std::env::temp_dir().join("parsec-poll-test.sock");
+        let listener = DomainSocketListenerBuilder::new()
+            .with_timeout(Duration::from_millis(100))
+            .with_socket_path(Some(socket_path.clone()))
+            .build()
+            .unwrap();
+        let poller = Poller::new().unwrap();
+
+        let ready = poller
+            .wait(&[&listener], Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(ready.is_empty());
+
+        let _client = UnixStream::connect(&socket_path).unwrap();
+        let ready = poller
+            .wait(&[&listener], Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(ready, vec![0]);
+        assert!(listener.accept().is_some());
+
+        let _ = std::fs::remove_file(socket_path);
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Per-application rate limiting
+//!
+//! Limits how often applications can send requests of a given opcode, so that one of them can not
+//! starve the others by flooding the service. Each application gets its own token bucket for each
+//! limited opcode.
+//!
+//! A full bucket behaves like a new one: full buckets are dropped once there are many buckets, so
+//! that only the buckets of the applications which recently sent requests are kept.
+use crate::authenticators::ApplicationIdentity;
+use crate::utils::config::{RateLimit, RateLimitsConfig};
+use log::error;
+use parsec_interface::requests::Opcode;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Number of buckets from which full buckets are dropped
+const MIN_SWEEP_THRESHOLD: usize = 1024;
+
+/// Enforces token bucket rate limits per application and per opcode
+///
+/// Applications which do not have limits of their own get the default ones. Requests sent without
+/// authentication all share the same buckets.
+#[derive(Debug)]
+pub struct RateLimiter {
+    default_limits: HashMap<Opcode, RateLimit>,
+    application_limits: HashMap<String, HashMap<Opcode, RateLimit>>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// Create a rate limiter enforcing the limits of the configuration.
+    ///
+    /// # Errors
+    /// - if a rate is not a positive number or a burst is 0, returns an `InvalidData` error
+    pub fn new(config: &RateLimitsConfig) -> std::io::Result<Self> {
+        let all_limits = config
+            .applications
+            .values()
+            .chain(std::iter::once(&config.default))
+            .flat_map(|limits| limits.0.iter());
+        for (opcode, limit) in all_limits {
+            if !(limit.rate.is_finite() && limit.rate > 0.0) || limit.burst == 0 {
+                error!(
+                    "The rate limit of {:?} must have a positive rate and a burst of at least 1.",
+                    opcode
+                );
+                return Err(Error::new(ErrorKind::InvalidData, "invalid rate limit"));
+            }
+        }
+
+        Ok(RateLimiter {
+            default_limits: config.default.0.clone(),
+            application_limits: config
+                .applications
+                .iter()
+                .map(|(name, limits)| (name.clone(), limits.0.clone()))
+                .collect(),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                sweep_threshold: MIN_SWEEP_THRESHOLD,
+            }),
+        })
+    }
+
+    /// Take a token for a request of `application` with the given opcode.
+    ///
+    /// Returns `false` if the application exceeded its limit and the request must be rejected.
+    pub fn allow(&self, application: Option<&ApplicationIdentity>, opcode: Opcode) -> bool {
+        let limit = match application
+            .and_then(|application| self.application_limits.get(application.name()))
+            .and_then(|limits| limits.get(&opcode))
+            .or_else(|| self.default_limits.get(&opcode))
+        {
+            Some(limit) => *limit,
+            None => return true,
+        };
+
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        if buckets.buckets.len() >= buckets.sweep_threshold {
+            buckets.sweep();
+        }
+        buckets
+            .buckets
+            .entry((application.cloned(), opcode))
+            .or_insert_with(|| TokenBucket::new(limit))
+            .take()
+    }
+}
+
+/// Token buckets of the applications, per opcode
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<(Option<ApplicationIdentity>, Opcode), TokenBucket>,
+    /// Number of buckets from which the next sweep is done
+    sweep_threshold: usize,
+}
+
+impl Buckets {
+    /// Drop the full buckets.
+    ///
+    /// The next sweep is done once the number of buckets doubled, so that sweeping stays cheap
+    /// even if most of the buckets are not full.
+    fn sweep(&mut self) {
+        let now = Instant::now();
+        self.buckets.retain(|_, bucket| !bucket.is_full(now));
+        self.sweep_threshold = (self.buckets.len() * 2).max(MIN_SWEEP_THRESHOLD);
+    }
+}
+
+/// Bucket refilled with tokens at a constant rate, up to its capacity
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        TokenBucket {
+            limit,
+            tokens: f64::from(limit.burst),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Number of tokens in the bucket at the given time.
+    fn tokens_at(&self, now: Instant) -> f64 {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        (self.tokens + elapsed * self.limit.rate).min(f64::from(self.limit.burst))
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        self.tokens_at(now) >= f64::from(self.limit.burst)
+    }
+
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        self.tokens = self.tokens_at(now);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RateLimiter, MIN_SWEEP_THRESHOLD};
+    use crate::authenticators::ApplicationIdentity;
+    use crate::utils::config::{PerOpcode, RateLimit, RateLimitsConfig};
+    use parsec_interface::requests::{AuthType, Opcode};
+    use std::collections::HashMap;
+
+    fn limits(opcode: Opcode, burst: u32) -> PerOpcode<RateLimit> {
+        let mut limits = HashMap::new();
+        // One token every thousand seconds: no token is added back during the tests.
+        let _ = limits.insert(opcode, RateLimit { rate: 0.001, burst });
+        PerOpcode(limits)
+    }
+
+    fn application(name: &str) -> ApplicationIdentity {
+        ApplicationIdentity::new(name.to_string(), AuthType::Direct)
+    }
+
+    #[test]
+    fn default_limit_per_application() {
+        let rate_limiter = RateLimiter::new(&RateLimitsConfig {
+            default: limits(Opcode::PsaGenerateKey, 2),
+            applications: HashMap::new(),
+        })
+        .unwrap();
+        let app_1 = application("app-1");
+        let app_2 = application("app-2");
+
+        assert!(rate_limiter.allow(Some(&app_1), Opcode::PsaGenerateKey));
+        assert!(rate_limiter.allow(Some(&app_1), Opcode::PsaGenerateKey));
+        assert!(!rate_limiter.allow(Some(&app_1), Opcode::PsaGenerateKey));
+        // Other applications and opcodes are not affected.
+        assert!(rate_limiter.allow(Some(&app_2), Opcode::PsaGenerateKey));
+        assert!(rate_limiter.allow(Some(&app_1), Opcode::PsaGenerateRandom));
+    }
+
+    #[test]
+    fn application_override() {
+        let mut applications = HashMap::new();
+        let _ = applications.insert("app-1".to_string(), limits(Opcode::PsaGenerateKey, 1));
+        let rate_limiter = RateLimiter::new(&RateLimitsConfig {
+            default: limits(Opcode::PsaGenerateKey, 3),
+            applications,
+        })
+        .unwrap();
+        let app_1 = application("app-1");
+
+        assert!(rate_limiter.allow(Some(&app_1), Opcode::PsaGenerateKey));
+        assert!(!rate_limiter.allow(Some(&app_1), Opcode::PsaGenerateKey));
+    }
+
+    #[test]
+    fn tokens_are_refilled() {
+        let mut default = HashMap::new();
+        let _ = default.insert(
+            Opcode::PsaGenerateRandom,
+            RateLimit {
+                rate: 1000.0,
+                burst: 1,
+            },
+        );
+        let rate_limiter = RateLimiter::new(&RateLimitsConfig {
+            default: PerOpcode(default),
+            applications: HashMap::new(),
+        })
+        .unwrap();
+
+        assert!(rate_limiter.allow(None, Opcode::PsaGenerateRandom));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(rate_limiter.allow(None, Opcode::PsaGenerateRandom));
+    }
+
+    #[test]
+    fn full_buckets_are_dropped() {
+        let mut default = limits(Opcode::PsaGenerateKey, 2);
+        let _ = default.0.insert(
+            Opcode::PsaGenerateRandom,
+            RateLimit {
+                rate: 1000.0,
+                burst: 1,
+            },
+        );
+        let rate_limiter = RateLimiter::new(&RateLimitsConfig {
+            default,
+            applications: HashMap::new(),
+        })
+        .unwrap();
+
+        for index in 1..MIN_SWEEP_THRESHOLD {
+            let app = application(&format!("app-{}", index));
+            assert!(rate_limiter.allow(Some(&app), Opcode::PsaGenerateRandom));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let app = application("app");
+        assert!(rate_limiter.allow(Some(&app), Opcode::PsaGenerateKey));
+
+        // The buckets of PsaGenerateRandom are full again and dropped, the one of PsaGenerateKey
+        // is not.
+        assert!(rate_limiter.allow(Some(&app), Opcode::PsaGenerateKey));
+        let buckets = rate_limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.buckets.len(), 1);
+        assert_eq!(buckets.sweep_threshold, MIN_SWEEP_THRESHOLD);
+        drop(buckets);
+        assert!(!rate_limiter.allow(Some(&app), Opcode::PsaGenerateKey));
+    }
+
+    #[test]
+    fn invalid_limits() {
+        for (rate, burst) in [
+            (0.0, 1),
+            (-1.0, 1),
+            (f64::NAN, 1),
+            (f64::INFINITY, 1),
+            (1.0, 0),
+        ] {
+            let mut default = HashMap::new();
+            let _ = default.insert(Opcode::PsaGenerateKey, RateLimit { rate, burst });
+            assert!(RateLimiter::new(&RateLimitsConfig {
+                default: PerOpcode(default),
+                applications: HashMap::new(),
+            })
+            .is_err());
+        }
+    }
+}
@@ -0,0 +1,216 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Capture of requests and responses
+//!
+//! To debug issues reported by clients, the front end handler can record the requests it receives
+//! and the responses it sends back into a capture file. The capture file is a sequence of
+//! request-response pairs, both in the Parsec wire format, exactly as they were received and sent,
+//! except for the authentication field of requests which is overwritten with zeros. It can be fed
+//! back to the service with the `parsec-replay` tool.
+//!
+//! The bodies of the requests and responses of operations carrying key material, plaintexts or
+//! random bytes, listed in `SECRET_OPCODES`, are overwritten with zeros as well: those requests
+//! can not be replayed successfully.
+//!
+//! Requests which could not be parsed are not recorded, and neither are the requests received by
+//! the HTTP gateway.
+use anyhow::{Context, Result};
+use parsec_interface::requests::Opcode;
+use std::fs::{File, OpenOptions, Permissions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Operations whose request or response body can contain key material, plaintexts or random bytes
+const SECRET_OPCODES: [Opcode; 10] = [
+    Opcode::PsaImportKey,
+    Opcode::PsaExportKey,
+    Opcode::PsaRawKeyAgreement,
+    Opcode::PsaGenerateRandom,
+    Opcode::PsaAsymmetricEncrypt,
+    Opcode::PsaAsymmetricDecrypt,
+    Opcode::PsaAeadEncrypt,
+    Opcode::PsaAeadDecrypt,
+    Opcode::PsaCipherEncrypt,
+    Opcode::PsaCipherDecrypt,
+];
+
+/// Records request-response pairs into a capture file
+#[derive(Debug)]
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    /// Append the captures to the file at the given path, which is created if it does not exist.
+    /// The captures contain data from clients: the file is only readable by the owner, even if it
+    /// already existed.
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)
+            .with_context(|| format!("Failed to open the capture file {}", path.display()))?;
+        file.set_permissions(Permissions::from_mode(0o600))
+            .with_context(|| {
+                format!(
+                    "Failed to restrict the permissions of the capture file {}",
+                    path.display()
+                )
+            })?;
+
+        Ok(Recorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Record a raw request for the `opcode` operation, whose body of `body_len` bytes is followed
+    /// by an authentication field of `auth_len` bytes, along with the raw response sent back,
+    /// which ends with a body of `response_body_len` bytes.
+    ///
+    /// Failing to record is logged but does not prevent the request from being served.
+    pub fn record(
+        &self,
+        opcode: Opcode,
+        mut request: Vec<u8>,
+        body_len: usize,
+        auth_len: usize,
+        response: &[u8],
+        response_body_len: usize,
+    ) {
+        let auth_start = request.len().saturating_sub(auth_len);
+        let redacted_start = if SECRET_OPCODES.contains(&opcode) {
+            auth_start.saturating_sub(body_len)
+        } else {
+            auth_start
+        };
+        request[redacted_start..]
+            .iter_mut()
+            .for_each(|byte| *byte = 0);
+        let mut redacted_response;
+        let response = if SECRET_OPCODES.contains(&opcode) {
+            redacted_response = response.to_vec();
+            let body_start = response.len().saturating_sub(response_body_len);
+            redacted_response[body_start..]
+                .iter_mut()
+                .for_each(|byte| *byte = 0);
+            &redacted_response
+        } else {
+            response
+        };
+
+        // Both are written while holding the lock so that pairs recorded concurrently do not
+        // interleave.
+        let mut file = self.file.lock().expect("Recorder lock poisoned");
+        if let Err(err) = file
+            .write_all(&request)
+            .and_then(|_| file.write_all(response))
+        {
+            format_error!("Failed to record the request", err);
+        }
+    }
+}
+
+/// Reader keeping a copy of the bytes read, if recording
+#[derive(Debug)]
+pub struct RecordingReader<R> {
+    inner: R,
+    recorded: Option<Vec<u8>>,
+}
+
+impl<R: Read> RecordingReader<R> {
+    /// Read from `inner`, keeping a copy of the bytes read if `record` is set.
+    pub fn new(inner: R, record: bool) -> Self {
+        RecordingReader {
+            inner,
+            recorded: if record { Some(Vec::new()) } else { None },
+        }
+    }
+
+    /// Bytes read so far, if recording.
+    pub fn into_recorded(self) -> Option<Vec<u8>> {
+        self.recorded
+    }
+}
+
+impl<R: Read> Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(&buf[..len]);
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Recorder, RecordingReader};
+    use parsec_interface::requests::Opcode;
+    use std::fs::Permissions;
+    use std::io::Read;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn authentication_is_redacted() {
+        let path = std::env::temp_dir().join("parsec-recorder-test.capture");
+        let _ = std::fs::remove_file(&path);
+        let recorder = Recorder::new(&path).unwrap();
+
+        let mut reader = RecordingReader::new(&[1, 2, 3, 4, 5][..], true);
+        let mut request = [0; 5];
+        reader.read_exact(&mut request).unwrap();
+        recorder.record(
+            Opcode::PsaSignHash,
+            reader.into_recorded().unwrap(),
+            1,
+            2,
+            &[6, 7],
+            1,
+        );
+        recorder.record(Opcode::Ping, vec![8], 0, 0, &[9], 0);
+
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            vec![1, 2, 3, 0, 0, 6, 7, 8, 9]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn secret_bodies_are_redacted() {
+        let path = std::env::temp_dir().join("parsec-recorder-secret-test.capture");
+        let _ = std::fs::remove_file(&path);
+        let recorder = Recorder::new(&path).unwrap();
+
+        recorder.record(
+            Opcode::PsaImportKey,
+            vec![1, 2, 3, 4, 5],
+            2,
+            1,
+            &[6, 7, 8],
+            2,
+        );
+        recorder.record(Opcode::PsaExportKey, vec![1, 2], 1, 0, &[3, 4], 1);
+
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            vec![1, 2, 0, 0, 0, 6, 0, 0, 1, 0, 3, 0]
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn existing_file_is_restricted() {
+        let path = std::env::temp_dir().join("parsec-recorder-permissions-test.capture");
+        std::fs::write(&path, []).unwrap();
+        std::fs::set_permissions(&path, Permissions::from_mode(0o644)).unwrap();
+
+        let _recorder = Recorder::new(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,466 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Service front using TCP with mutual TLS
+//!
+//! Expose Parsec functionality to remote clients over TCP. Every connection is protected with TLS
+//! and clients have to present a certificate signed by one of the configured certificate
+//! authorities. The identity found in the verified client certificate is passed on as connection
+//! metadata.
+//!
+//! The TLS handshake needs messages from the client, which could stall it: it is not done when the
+//! connection is accepted but by the thread serving it, before reading the first request.
+use super::listener;
+use anyhow::{Context, Result};
+use listener::Listen;
+use listener::{Connection, ConnectionMetadata, Protocol};
+use log::error;
+use picky_asn1_x509::Certificate as X509Certificate;
+use rustls::server::AllowAnyAuthenticatedClient;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+use rustls_pemfile::Item;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// TCP with mutual TLS IPC manager
+///
+/// Listener implementation for TLS-protected TCP connections as the underlying IPC mechanism.
+///
+/// Holds references to a `TcpListener` and to the TLS configuration shared by all connections.
+#[derive(Debug)]
+pub struct TcpTlsListener {
+    listener: TcpListener,
+    timeout: Duration,
+    tls_config: Arc<ServerConfig>,
+}
+
+impl TcpTlsListener {
+    /// Bind to the TCP address given and serve connections with the TLS configuration.
+    pub fn new(timeout: Duration, address: String, tls_config: Arc<ServerConfig>) -> Result<Self> {
+        let listener = TcpListener::bind(&address)
+            .with_context(|| format!("Failed to bind to TCP address {}", address))?;
+        listener.set_nonblocking(true)?;
+
+        Ok(Self {
+            listener,
+            timeout,
+            tls_config,
+        })
+    }
+}
+
+impl Listen for TcpTlsListener {
+    fn set_timeout(&mut self, duration: Duration) {
+        self.timeout = duration;
+    }
+
+    fn accept(&self) -> Option<Connection> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                // Check if the error is because no connections are currently present.
+                if err.kind() != ErrorKind::WouldBlock {
+                    // Only log the real errors.
+                    format_error!("Failed to connect with a TcpStream", err);
+                }
+                return None;
+            }
+        };
+
+        if let Err(err) = stream.set_read_timeout(Some(self.timeout)) {
+            format_error!("Failed to set read timeout", err);
+            return None;
+        }
+        if let Err(err) = stream.set_write_timeout(Some(self.timeout)) {
+            format_error!("Failed to set write timeout", err);
+            return None;
+        }
+        if let Err(err) = stream.set_nonblocking(false) {
+            format_error!("Failed to set stream as blocking", err);
+            return None;
+        }
+
+        let tls_connection = match ServerConnection::new(self.tls_config.clone()) {
+            Ok(tls_connection) => tls_connection,
+            Err(err) => {
+                format_error!("Failed to create the TLS connection", err);
+                return None;
+            }
+        };
+        let stream = Arc::new(Mutex::new(StreamOwned::new(tls_connection, stream)));
+        let timeout = self.timeout;
+        let handshake_stream = stream.clone();
+
+        Some(Connection {
+            stream: Box::new(TlsStream(stream)),
+            metadata: None,
+            permitted_authenticators: None,
+            protocol: Protocol::Parsec,
+            handshake: Some(Box::new(move || {
+                let identity = handshake(&handshake_stream, timeout)?;
+                Some(ConnectionMetadata::TlsClientCertificate { identity })
+            })),
+        })
+    }
+}
+
+impl AsRawFd for TcpTlsListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+type TlsConnection = Mutex<StreamOwned<ServerConnection, TcpStream>>;
+
+/// Perform the TLS handshake, within the listener timeout, and return the identity found in the
+/// client certificate.
+fn handshake(stream: &TlsConnection, timeout: Duration) -> Option<String> {
+    let mut stream = stream.lock().expect("TLS stream lock poisoned");
+    let StreamOwned { conn, sock } = &mut *stream;
+    let started = Instant::now();
+    while conn.is_handshaking() {
+        if started.elapsed() > timeout {
+            error!("The TLS handshake did not complete in time.");
+            return None;
+        }
+        if let Err(err) = conn.complete_io(sock) {
+            format_error!("TLS handshake failed", err);
+            return None;
+        }
+    }
+
+    let certificate = match conn
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+    {
+        Some(certificate) => certificate,
+        None => {
+            error!("The client did not present any certificate.");
+            return None;
+        }
+    };
+
+    client_identity(certificate)
+}
+
+/// TLS stream notifying the client that the connection is closed when it is dropped.
+///
+/// The TLS connection is shared with the handshake of the `Connection`.
+struct TlsStream(Arc<TlsConnection>);
+
+impl TlsStream {
+    fn lock(&self) -> MutexGuard<'_, StreamOwned<ServerConnection, TcpStream>> {
+        self.0.lock().expect("TLS stream lock poisoned")
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        let mut stream = self.lock();
+        // Completing the handshake to notify the client could block: connections dropped before
+        // their handshake are closed straight away.
+        if !stream.conn.is_handshaking() {
+            let StreamOwned { conn, sock } = &mut *stream;
+            conn.send_close_notify();
+            let _ = conn.complete_io(sock);
+        }
+    }
+}
+
+/// Get the identity of a client out of its certificate: its subject common name or, if there is
+/// none, its whole subject distinguished name.
+fn client_identity(certificate: &Certificate) -> Option<String> {
+    let certificate: X509Certificate = picky_asn1_der::from_bytes(&certificate.0)
+        .map_err(|err| {
+            format_error!("Failed to parse the client certificate", err);
+        })
+        .ok()?;
+    let subject = &certificate.tbs_certificate.subject;
+
+    let identity = match subject.find_common_name() {
+        Some(common_name) => common_name.to_utf8_lossy().into_owned(),
+        None => subject.to_string(),
+    };
+    if identity.is_empty() {
+        error!("The client certificate does not have a subject.");
+        return None;
+    }
+
+    Some(identity)
+}
+
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    );
+    let certificates: Vec<Certificate> = rustls_pemfile::certs(&mut reader)
+        .with_context(|| format!("Failed to parse certificates from {}", path.display()))?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if certificates.is_empty() {
+        error!("No certificate found in {}.", path.display());
+        return Err(Error::new(ErrorKind::InvalidData, "no certificate found").into());
+    }
+
+    Ok(certificates)
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let mut reader = BufReader::new(
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?,
+    );
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)
+        .with_context(|| format!("Failed to parse private key from {}", path.display()))?
+    {
+        match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+            _ => continue,
+        }
+    }
+
+    error!("No private key found in {}.", path.display());
+    Err(Error::new(ErrorKind::InvalidData, "no private key found").into())
+}
+
+/// Builder for `TcpTlsListener`
+#[derive(Clone, Debug, Default)]
+pub struct TcpTlsListenerBuilder {
+    timeout: Option<Duration>,
+    address: Option<String>,
+    server_certificate: Option<PathBuf>,
+    server_private_key: Option<PathBuf>,
+    client_ca_certificates: Option<PathBuf>,
+}
+
+impl TcpTlsListenerBuilder {
+    /// Create a new TcpTlsListener builder
+    pub fn new() -> Self {
+        TcpTlsListenerBuilder {
+            timeout: None,
+            address: None,
+            server_certificate: None,
+            server_private_key: None,
+            client_ca_certificates: None,
+        }
+    }
+
+    /// Add a timeout on the TCP connections
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Specify the TCP address to listen on
+    pub fn with_address(mut self, address: Option<String>) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Specify the path of the PEM file containing the server certificate chain
+    pub fn with_server_certificate(mut self, server_certificate: Option<PathBuf>) -> Self {
+        self.server_certificate = server_certificate;
+        self
+    }
+
+    /// Specify the path of the PEM file containing the server private key
+    pub fn with_server_private_key(mut self, server_private_key: Option<PathBuf>) -> Self {
+        self.server_private_key = server_private_key;
+        self
+    }
+
+    /// Specify the path of the PEM file containing the certificate authorities trusted to sign
+    /// client certificates
+    pub fn with_client_ca_certificates(mut self, client_ca_certificates: Option<PathBuf>) -> Self {
+        self.client_ca_certificates = client_ca_certificates;
+        self
+    }
+
+    /// Build the builder into the listener
+    pub fn build(self) -> Result<TcpTlsListener> {
+        let timeout = self.timeout.ok_or_else(|| {
+            error!("The listener timeout was not set.");
+            Error::new(ErrorKind::InvalidInput, "listener timeout missing")
+        })?;
+        let address = self.address.ok_or_else(|| {
+            error!("The TCP address to listen on was not set.");
+            Error::new(ErrorKind::InvalidInput, "listener address missing")
+        })?;
+        let server_certificate = self.server_certificate.ok_or_else(|| {
+            error!("The server certificate was not set.");
+            Error::new(ErrorKind::InvalidInput, "server certificate missing")
+        })?;
+        let server_private_key = self.server_private_key.ok_or_else(|| {
+            error!("The server private key was not set.");
+            Error::new(ErrorKind::InvalidInput, "server private key missing")
+        })?;
+        let client_ca_certificates = self.client_ca_certificates.ok_or_else(|| {
+            error!("The client CA certificates were not set.");
+            Error::new(ErrorKind::InvalidInput, "client CA certificates missing")
+        })?;
+
+        let mut client_roots = RootCertStore::empty();
+        for certificate in load_certificates(&client_ca_certificates)? {
+            client_roots
+                .add(&certificate)
+                .context("Failed to add a client CA certificate")?;
+        }
+
+        let tls_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(client_roots).boxed())
+            .with_single_cert(
+                load_certificates(&server_certificate)?,
+                load_private_key(&server_private_key)?,
+            )
+            .context("Invalid server certificate or private key")?;
+
+        TcpTlsListener::new(timeout, address, Arc::new(tls_config))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{load_certificates, load_private_key, TcpTlsListener, TcpTlsListenerBuilder};
+    use crate::front::listener::{Connection, ConnectionMetadata, Listen};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+    use std::convert::TryInto;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/test/tls")).join(name)
+    }
+
+    fn listener() -> TcpTlsListener {
+        TcpTlsListenerBuilder::new()
+            .with_timeout(Duration::from_secs(1))
+            .with_address(Some("127.0.0.1:0".to_string()))
+            .with_server_certificate(Some(fixture("server.pem")))
+            .with_server_private_key(Some(fixture("server.key")))
+            .with_client_ca_certificates(Some(fixture("ca.pem")))
+            .build()
+            .unwrap()
+    }
+
+    fn accept(listener: &TcpTlsListener) -> Connection {
+        for _ in 0..50 {
+            if let Some(connection) = listener.accept() {
+                return connection;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no connection accepted");
+    }
+
+    /// Connect to the listener with TLS, presenting the given client certificate, and exchange a
+    /// message with the server.
+    fn client(listener: &TcpTlsListener, certificate: Option<&str>) -> thread::JoinHandle<()> {
+        let address = listener.listener.local_addr().unwrap();
+        let mut roots = RootCertStore::empty();
+        for certificate in load_certificates(&fixture("ca.pem")).unwrap() {
+            roots.add(&certificate).unwrap();
+        }
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots);
+        let config = match certificate {
+            Some(name) => config
+                .with_client_auth_cert(
+                    load_certificates(&fixture(&format!("{}.pem", name))).unwrap(),
+                    load_private_key(&fixture(&format!("{}.key", name))).unwrap(),
+                )
+                .unwrap(),
+            None => config.with_no_client_auth(),
+        };
+
+        thread::spawn(move || {
+            let connection =
+                ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+            let stream = TcpStream::connect(address).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            let mut stream = StreamOwned::new(connection, stream);
+            // The server rejecting the client certificate makes these fail.
+            let mut pong = [0; 4];
+            if stream.write_all(b"ping").is_ok() && stream.read_exact(&mut pong).is_ok() {
+                assert_eq!(&pong, b"pong");
+            }
+        })
+    }
+
+    #[test]
+    fn client_certificate_identity() {
+        let listener = listener();
+        let client = client(&listener, Some("client"));
+
+        let mut connection = accept(&listener);
+        assert!(connection.metadata.is_none());
+        assert!(connection.complete_handshake());
+        match &connection.metadata {
+            Some(ConnectionMetadata::TlsClientCertificate { identity }) => {
+                assert_eq!(identity, "parsec-client")
+            }
+            metadata => panic!("unexpected metadata: {:?}", metadata),
+        }
+
+        let mut ping = [0; 4];
+        connection.stream.read_exact(&mut ping).unwrap();
+        assert_eq!(&ping, b"ping");
+        connection.stream.write_all(b"pong").unwrap();
+        connection.stream.flush().unwrap();
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn missing_or_untrusted_client_certificate() {
+        let listener = listener();
+        for certificate in [None, Some("other-client")] {
+            let client = client(&listener, certificate);
+            let mut connection = accept(&listener);
+            assert!(!connection.complete_handshake());
+            assert!(connection.metadata.is_none());
+            drop(connection);
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn stalled_client_does_not_block_accept() {
+        let listener = listener();
+        let address = listener.listener.local_addr().unwrap();
+        let _stalled = TcpStream::connect(address).unwrap();
+
+        let started = Instant::now();
+        let mut connection = accept(&listener);
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(!connection.complete_handshake());
+    }
+}
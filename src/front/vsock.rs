@@ -0,0 +1,291 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Service front using AF_VSOCK sockets
+//!
+//! Expose Parsec functionality to virtual machine guests over virtio-vsock, without any network
+//! stack involved. The context identifier (CID) of the connecting guest is passed on as connection
+//! metadata.
+use super::listener;
+use anyhow::{Context, Result};
+use libc::{c_int, c_void, sockaddr, sockaddr_vm, socklen_t, timeval, AF_VSOCK};
+use listener::Listen;
+use listener::{Connection, ConnectionMetadata, Protocol};
+use log::error;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::ptr;
+use std::time::Duration;
+
+/// Default port on which the listener waits for connections
+const DEFAULT_VSOCK_PORT: u32 = 3022;
+
+/// AF_VSOCK IPC manager
+///
+/// Listener implementation for virtio-vsock sockets as the underlying IPC mechanism.
+///
+/// Holds the file descriptor of the listening socket, which is closed on drop.
+#[derive(Debug)]
+pub struct VsockListener {
+    fd: RawFd,
+    timeout: Duration,
+}
+
+impl VsockListener {
+    /// Bind to the given CID and port. `libc::VMADDR_CID_ANY` can be used to accept connections
+    /// on any CID of the host.
+    pub fn new(timeout: Duration, cid: u32, port: u32) -> Result<Self> {
+        // Safe as no pointer is involved; the result is checked below.
+        let fd = unsafe {
+            libc::socket(
+                AF_VSOCK,
+                libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                0,
+            )
+        };
+        if fd < 0 {
+            return Err(Error::last_os_error()).context("Failed to create an AF_VSOCK socket");
+        }
+        // From now on, the file descriptor is closed when the listener is dropped, including on
+        // error.
+        let listener = VsockListener { fd, timeout };
+
+        let address = vsock_address(cid, port);
+        // Safe as the pointer and length given describe the address structure above.
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                ptr::addr_of!(address).cast::<sockaddr>(),
+                mem::size_of::<sockaddr_vm>() as socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(Error::last_os_error())
+                .with_context(|| format!("Failed to bind to vsock CID {} port {}", cid, port));
+        }
+
+        // Safe as no pointer is involved; the result is checked below.
+        if unsafe { libc::listen(fd, libc::SOMAXCONN) } < 0 {
+            return Err(Error::last_os_error()).context("Failed to listen on the AF_VSOCK socket");
+        }
+
+        Ok(listener)
+    }
+}
+
+impl Listen for VsockListener {
+    fn set_timeout(&mut self, duration: Duration) {
+        self.timeout = duration;
+    }
+
+    fn accept(&self) -> Option<Connection> {
+        let mut peer = vsock_address(0, 0);
+        let mut peer_len = mem::size_of::<sockaddr_vm>() as socklen_t;
+        // Safe as the pointers given describe the peer address structure above. The stream is
+        // blocking as SOCK_NONBLOCK is not part of the flags.
+        let fd = unsafe {
+            libc::accept4(
+                self.fd,
+                ptr::addr_of_mut!(peer).cast::<sockaddr>(),
+                &mut peer_len,
+                libc::SOCK_CLOEXEC,
+            )
+        };
+        if fd < 0 {
+            let err = Error::last_os_error();
+            // Check if the error is because no connections are currently present.
+            if err.kind() != ErrorKind::WouldBlock {
+                // Only log the real errors.
+                format_error!("Failed to connect with a vsock stream", err);
+            }
+            return None;
+        }
+        // Safe as the file descriptor was just returned by accept4 and is owned by nothing else.
+        let stream = VsockStream(unsafe { File::from_raw_fd(fd) });
+
+        if let Err(err) = stream.set_timeout(libc::SO_RCVTIMEO, self.timeout) {
+            format_error!("Failed to set read timeout", err);
+            return None;
+        }
+        if let Err(err) = stream.set_timeout(libc::SO_SNDTIMEO, self.timeout) {
+            format_error!("Failed to set write timeout", err);
+            return None;
+        }
+
+        Some(Connection {
+            stream: Box::new(stream),
+            metadata: Some(ConnectionMetadata::VsockPeer { cid: peer.svm_cid }),
+            permitted_authenticators: None,
+            protocol: Protocol::Parsec,
+            handshake: None,
+        })
+    }
+}
+
+impl AsRawFd for VsockListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for VsockListener {
+    fn drop(&mut self) {
+        // Safe as the file descriptor is owned by the listener.
+        let _ = unsafe { libc::close(self.fd) };
+    }
+}
+
+fn vsock_address(cid: u32, port: u32) -> sockaddr_vm {
+    // Safe as an all-zero sockaddr_vm is valid: it only contains integers.
+    let mut address: sockaddr_vm = unsafe { mem::zeroed() };
+    address.svm_family = AF_VSOCK as libc::sa_family_t;
+    address.svm_cid = cid;
+    address.svm_port = port;
+    address
+}
+
+/// Connected AF_VSOCK stream
+///
+/// `File` is only used to own the file descriptor and to read and write on it.
+struct VsockStream(File);
+
+impl VsockStream {
+    fn set_timeout(&self, option: c_int, timeout: Duration) -> std::io::Result<()> {
+        let timeout = timeval {
+            tv_sec: libc::time_t::try_from(timeout.as_secs()).unwrap_or(libc::time_t::MAX),
+            tv_usec: libc::suseconds_t::from(timeout.subsec_micros()),
+        };
+        // Safe as the pointer and length given describe the timeval structure above.
+        let ret = unsafe {
+            libc::setsockopt(
+                self.0.as_raw_fd(),
+                libc::SOL_SOCKET,
+                option,
+                ptr::addr_of!(timeout).cast::<c_void>(),
+                mem::size_of::<timeval>() as socklen_t,
+            )
+        };
+        if ret < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Read for VsockStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for VsockStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Builder for `VsockListener`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct VsockListenerBuilder {
+    timeout: Option<Duration>,
+    cid: Option<u32>,
+    port: Option<u32>,
+}
+
+impl VsockListenerBuilder {
+    /// Create a new VsockListener builder
+    pub fn new() -> Self {
+        VsockListenerBuilder {
+            timeout: None,
+            cid: None,
+            port: None,
+        }
+    }
+
+    /// Add a timeout on the vsock connections
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Specify the CID to bind to. Defaults to any CID.
+    pub fn with_cid(mut self, cid: Option<u32>) -> Self {
+        self.cid = cid;
+        self
+    }
+
+    /// Specify the port to listen on
+    pub fn with_port(mut self, port: Option<u32>) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Build the builder into the listener
+    pub fn build(self) -> Result<VsockListener> {
+        VsockListener::new(
+            self.timeout.ok_or_else(|| {
+                error!("The listener timeout was not set.");
+                Error::new(ErrorKind::InvalidInput, "listener timeout missing")
+            })?,
+            self.cid.unwrap_or(libc::VMADDR_CID_ANY),
+            self.port.unwrap_or(DEFAULT_VSOCK_PORT),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{vsock_address, VsockListenerBuilder};
+    use crate::front::listener::{ConnectionMetadata, Listen};
+    use libc::{sockaddr, sockaddr_vm, socklen_t, AF_VSOCK, VMADDR_CID_LOCAL};
+    use std::mem;
+    use std::ptr;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    #[ignore = "needs the vsock_loopback kernel module"]
+    fn loopback_connection() {
+        let port = 52022;
+        let listener = VsockListenerBuilder::new()
+            .with_timeout(Duration::from_millis(100))
+            .with_cid(Some(VMADDR_CID_LOCAL))
+            .with_port(Some(port))
+            .build()
+            .unwrap();
+
+        let client = unsafe { libc::socket(AF_VSOCK, libc::SOCK_STREAM, 0) };
+        assert!(client >= 0);
+        let address = vsock_address(VMADDR_CID_LOCAL, port);
+        let ret = unsafe {
+            libc::connect(
+                client,
+                ptr::addr_of!(address).cast::<sockaddr>(),
+                mem::size_of::<sockaddr_vm>() as socklen_t,
+            )
+        };
+        assert_eq!(ret, 0);
+
+        let mut connection = None;
+        for _ in 0..50 {
+            connection = listener.accept();
+            if connection.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        match connection.expect("no connection accepted").metadata {
+            Some(ConnectionMetadata::VsockPeer { cid }) => assert_eq!(cid, VMADDR_CID_LOCAL),
+            metadata => panic!("unexpected metadata: {:?}", metadata),
+        }
+
+        let _ = unsafe { libc::close(client) };
+    }
+}
@@ -447,7 +447,7 @@ impl KeyInfoManagerClient {
 }
 
 /// Builder for KeyInfoManager clients
-#[derive(Derivative)]
+#[derive(Clone, Derivative)]
 #[derivative(Debug)]
 pub struct KeyInfoManagerFactory {
     #[derivative(Debug = "ignore")]
@@ -208,6 +208,7 @@ pub struct ProviderBuilder {
     version_min: Option<u8>,
     #[derivative(Debug = "ignore")]
     prov_list: Vec<Arc<dyn Provide + Send + Sync>>,
+    prov_names: Vec<Option<String>>,
     #[derivative(Debug = "ignore")]
     authenticator_info: Vec<AuthenticatorInfo>,
 }
@@ -219,6 +220,7 @@ impl ProviderBuilder {
             version_maj: None,
             version_min: None,
             prov_list: Vec::new(),
+            prov_names: Vec::new(),
             authenticator_info: Vec::new(),
         }
     }
@@ -234,6 +236,23 @@ impl ProviderBuilder {
     /// Add a provider used
     pub fn with_provider(mut self, provider: Arc<dyn Provide + Send + Sync>) -> Self {
         self.prov_list.push(provider);
+        self.prov_names.push(None);
+
+        self
+    }
+
+    /// Add a provider used, with its name
+    ///
+    /// If several providers of the same type are used, their name is added to their description
+    /// so that clients can tell them apart. Requests addressed with the ID of their type go to the
+    /// first of them: the description of the others says that they can not be addressed that way.
+    pub fn with_named_provider(
+        mut self,
+        name: String,
+        provider: Arc<dyn Provide + Send + Sync>,
+    ) -> Self {
+        self.prov_list.push(provider);
+        self.prov_names.push(Some(name));
 
         self
     }
@@ -258,9 +277,30 @@ impl ProviderBuilder {
             let (provider_info, opcodes) = provider
                 .describe()
                 .map_err(|_| Error::new(ErrorKind::Other, "Failed to describe provider"))?;
-            let _ = provider_opcodes.insert(provider_info.id, opcodes);
+            // The opcodes of a provider ID are the ones of the first provider of that type, the one
+            // requests are dispatched to.
+            let _ = provider_opcodes.entry(provider_info.id).or_insert(opcodes);
             provider_info_vec.push(provider_info);
         }
+        let ids: Vec<ProviderId> = provider_info_vec.iter().map(|info| info.id).collect();
+        for (index, (provider_info, name)) in provider_info_vec
+            .iter_mut()
+            .zip(&self.prov_names)
+            .enumerate()
+        {
+            if let Some(name) = name {
+                if ids[..index].contains(&provider_info.id) {
+                    provider_info.description = format!(
+                        "{} (instance \"{}\", not addressable by provider ID: only reachable by \
+                        name through the HTTP gateway or with automatic provider selection)",
+                        provider_info.description, name
+                    );
+                } else if ids[index + 1..].contains(&provider_info.id) {
+                    provider_info.description =
+                        format!("{} (instance \"{}\")", provider_info.description, name);
+                }
+            }
+        }
 
         let crate_version: std::result::Result<Vec<u32>, ParseIntError> = env!("CARGO_PKG_VERSION")
             .split('.')
@@ -350,4 +390,46 @@ mod tests {
             "error building a CoreProvider"
         )
     }
+
+    #[test]
+    fn later_instances_are_not_addressable() {
+        use crate::providers::mock::MockProvider;
+
+        let provider = ProviderBuilder::new()
+            .with_wire_protocol_version(0, 1)
+            .with_named_provider(
+                "token-1".to_string(),
+                Arc::new(
+                    MockProvider::new(ProviderId::Pkcs11)
+                        .with_psa_generate_random(|_| Err(ResponseStatus::PsaErrorNotSupported)),
+                ),
+            )
+            .with_named_provider(
+                "token-2".to_string(),
+                Arc::new(MockProvider::new(ProviderId::Pkcs11)),
+            )
+            .build()
+            .unwrap();
+
+        let providers = provider
+            .list_providers(list_providers::Operation {})
+            .unwrap()
+            .providers;
+        assert_eq!(
+            providers[0].description,
+            "Mock provider (instance \"token-1\")"
+        );
+        assert!(providers[1]
+            .description
+            .starts_with("Mock provider (instance \"token-2\", not addressable by provider ID"));
+
+        // The opcodes of the provider ID are the ones of the instance requests are sent to.
+        let opcodes = provider
+            .list_opcodes(list_opcodes::Operation {
+                provider_id: ProviderId::Pkcs11,
+            })
+            .unwrap()
+            .opcodes;
+        assert!(opcodes.contains(&Opcode::PsaGenerateRandom));
+    }
 }
@@ -0,0 +1,168 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Mock provider for unit tests
+//!
+//! The `MockProvider` executes the operations it was given an implementation of, and holds the
+//! keys it was given the names of. All the other operations return `PsaErrorNotSupported`.
+use super::Provide;
+use crate::authenticators::ApplicationIdentity;
+use parsec_interface::operations::list_providers::ProviderInfo;
+use parsec_interface::operations::psa_algorithm::Algorithm;
+use parsec_interface::operations::psa_key_attributes::{
+    Attributes, Lifetime, Policy, Type, UsageFlags,
+};
+use parsec_interface::operations::{
+    list_clients, list_keys, ping, psa_generate_random, psa_sign_hash,
+};
+use parsec_interface::requests::{Opcode, ProviderId, ResponseStatus, Result};
+use std::collections::HashSet;
+
+/// Implementation of an operation of the mock provider
+type Operation<O, R> = Box<dyn Fn(O) -> Result<R> + Send + Sync>;
+
+/// Provider whose operations are implemented by closures
+#[allow(missing_debug_implementations)]
+pub struct MockProvider {
+    id: ProviderId,
+    key_names: Vec<String>,
+    ping: Option<Operation<ping::Operation, ping::Result>>,
+    psa_sign_hash: Option<Operation<psa_sign_hash::Operation, psa_sign_hash::Result>>,
+    psa_generate_random:
+        Option<Operation<psa_generate_random::Operation, psa_generate_random::Result>>,
+}
+
+impl MockProvider {
+    /// Create a mock provider of the given type, without keys or operations.
+    pub fn new(id: ProviderId) -> Self {
+        MockProvider {
+            id,
+            key_names: Vec::new(),
+            ping: None,
+            psa_sign_hash: None,
+            psa_generate_random: None,
+        }
+    }
+
+    /// Hold a key with the given name, for all applications.
+    pub fn with_key(mut self, key_name: &str) -> Self {
+        self.key_names.push(key_name.to_string());
+        self
+    }
+
+    /// Implement Ping with `ping`.
+    pub fn with_ping<F>(mut self, ping: F) -> Self
+    where
+        F: Fn(ping::Operation) -> Result<ping::Result> + Send + Sync + 'static,
+    {
+        self.ping = Some(Box::new(ping));
+        self
+    }
+
+    /// Implement PsaSignHash with `sign_hash`.
+    pub fn with_psa_sign_hash<F>(mut self, sign_hash: F) -> Self
+    where
+        F: Fn(psa_sign_hash::Operation) -> Result<psa_sign_hash::Result> + Send + Sync + 'static,
+    {
+        self.psa_sign_hash = Some(Box::new(sign_hash));
+        self
+    }
+
+    /// Implement PsaGenerateRandom with `generate_random`.
+    pub fn with_psa_generate_random<F>(mut self, generate_random: F) -> Self
+    where
+        F: Fn(psa_generate_random::Operation) -> Result<psa_generate_random::Result>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.psa_generate_random = Some(Box::new(generate_random));
+        self
+    }
+}
+
+/// Execute the operation if the mock provider implements it.
+fn execute<O, R>(operation: &Option<Operation<O, R>>, op: O) -> Result<R> {
+    match operation {
+        Some(operation) => operation(op),
+        None => Err(ResponseStatus::PsaErrorNotSupported),
+    }
+}
+
+impl Provide for MockProvider {
+    fn describe(&self) -> Result<(ProviderInfo, HashSet<Opcode>)> {
+        let mut opcodes = HashSet::new();
+        for (opcode, implemented) in [
+            (Opcode::Ping, self.ping.is_some()),
+            (Opcode::PsaSignHash, self.psa_sign_hash.is_some()),
+            (
+                Opcode::PsaGenerateRandom,
+                self.psa_generate_random.is_some(),
+            ),
+        ] {
+            if implemented {
+                let _ = opcodes.insert(opcode);
+            }
+        }
+        Ok((
+            ProviderInfo {
+                uuid: uuid::Uuid::nil(),
+                description: String::from("Mock provider"),
+                vendor: String::new(),
+                version_maj: 0,
+                version_min: 0,
+                version_rev: 0,
+                id: self.id,
+            },
+            opcodes,
+        ))
+    }
+
+    fn list_keys(
+        &self,
+        _application_identity: &ApplicationIdentity,
+        _op: list_keys::Operation,
+    ) -> Result<list_keys::Result> {
+        Ok(list_keys::Result {
+            keys: self
+                .key_names
+                .iter()
+                .map(|key_name| list_keys::KeyInfo {
+                    provider_id: self.id,
+                    name: key_name.clone(),
+                    attributes: Attributes {
+                        lifetime: Lifetime::Persistent,
+                        key_type: Type::RawData,
+                        bits: 0,
+                        policy: Policy {
+                            usage_flags: UsageFlags::default(),
+                            permitted_algorithms: Algorithm::None,
+                        },
+                    },
+                })
+                .collect(),
+        })
+    }
+
+    fn list_clients(&self, _op: list_clients::Operation) -> Result<list_clients::Result> {
+        Err(ResponseStatus::PsaErrorNotSupported)
+    }
+
+    fn ping(&self, op: ping::Operation) -> Result<ping::Result> {
+        execute(&self.ping, op)
+    }
+
+    fn psa_sign_hash(
+        &self,
+        _application_identity: &ApplicationIdentity,
+        op: psa_sign_hash::Operation,
+    ) -> Result<psa_sign_hash::Result> {
+        execute(&self.psa_sign_hash, op)
+    }
+
+    fn psa_generate_random(
+        &self,
+        op: psa_generate_random::Operation,
+    ) -> Result<psa_generate_random::Result> {
+        execute(&self.psa_generate_random, op)
+    }
+}
@@ -16,6 +16,11 @@ pub mod core;
 
 pub mod crypto_capability;
 
+pub mod supervisor;
+
+#[cfg(test)]
+pub(crate) mod mock;
+
 #[cfg(feature = "pkcs11-provider")]
 //TODO: To remove when #301 is merged
 #[allow(clippy::all)]
@@ -118,6 +123,14 @@ pub trait Provide {
     /// The descriptions are gathered in the Core Provider and returned for a ListProviders operation.
     fn describe(&self) -> Result<(list_providers::ProviderInfo, HashSet<Opcode>)>;
 
+    /// Check whether the provider is able to serve requests.
+    ///
+    /// Providers are healthy unless they are supervised and failed because of their underlying
+    /// hardware.
+    fn is_healthy(&self) -> bool {
+        true
+    }
+
     /// List the providers running in the service.
     fn list_providers(&self, _op: list_providers::Operation) -> Result<list_providers::Result> {
         trace!("list_providers ingress");
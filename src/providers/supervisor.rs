@@ -0,0 +1,526 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Provider supervision
+//!
+//! A supervised provider wraps another provider and watches the results of its operations. When
+//! one of them reports a communication or hardware failure, for example because a PKCS 11 token
+//! was unplugged or the TPM resource manager restarted, the provider is probed by generating a
+//! random byte: these statuses are also returned for failures of a single operation, so the
+//! provider is only marked as unhealthy if the probe fails as well. A background thread then
+//! re-initialises it, by dropping it and creating it again, until it succeeds.
+use super::Provide;
+use crate::authenticators::ApplicationIdentity;
+use derivative::Derivative;
+use log::{info, warn};
+use parsec_interface::operations::{
+    attest_key, can_do_crypto, delete_client, list_authenticators, list_clients, list_keys,
+    list_opcodes, list_providers, ping, prepare_key_attestation, psa_aead_decrypt,
+    psa_aead_encrypt, psa_asymmetric_decrypt, psa_asymmetric_encrypt, psa_cipher_decrypt,
+    psa_cipher_encrypt, psa_destroy_key, psa_export_key, psa_export_public_key, psa_generate_key,
+    psa_generate_random, psa_hash_compare, psa_hash_compute, psa_import_key, psa_raw_key_agreement,
+    psa_sign_hash, psa_sign_message, psa_verify_hash, psa_verify_message,
+};
+use parsec_interface::requests::{Opcode, ResponseStatus, Result};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, TryLockError};
+use std::thread;
+use std::time::Duration;
+
+/// Function creating a new instance of a provider
+pub type ProviderFactory =
+    Box<dyn Fn() -> anyhow::Result<Arc<dyn Provide + Send + Sync>> + Send + Sync>;
+
+/// Provider re-initialised after failures
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct SupervisedProvider {
+    name: String,
+    /// `None` if the provider could not be re-initialised
+    #[derivative(Debug = "ignore")]
+    provider: RwLock<Option<Arc<dyn Provide + Send + Sync>>>,
+    #[derivative(Debug = "ignore")]
+    factory: ProviderFactory,
+    healthy: AtomicBool,
+}
+
+impl SupervisedProvider {
+    /// Supervise the provider with the given name, which can be created again with `factory`.
+    pub fn new(
+        name: String,
+        provider: Arc<dyn Provide + Send + Sync>,
+        factory: ProviderFactory,
+    ) -> Self {
+        SupervisedProvider {
+            name,
+            provider: RwLock::new(Some(provider)),
+            factory,
+            healthy: AtomicBool::new(true),
+        }
+    }
+
+    /// Start a background thread trying to re-initialise the provider, if it is unhealthy, every
+    /// `interval`. The thread stops once the supervised provider is dropped.
+    pub fn supervise(supervised: &Arc<SupervisedProvider>, interval: Duration) {
+        let supervised = Arc::downgrade(supervised);
+        let _ = thread::spawn(move || loop {
+            thread::sleep(interval);
+            match supervised.upgrade() {
+                Some(supervised) => {
+                    let _ = supervised.recover();
+                }
+                None => break,
+            }
+        });
+    }
+
+    /// Re-initialise the provider if it is unhealthy.
+    ///
+    /// The provider is only re-initialised once none of its operations is in progress: waiting
+    /// for operations stuck on the failed hardware could block forever, so the attempt is skipped
+    /// if there are some.
+    ///
+    /// Returns `true` if the provider is healthy afterwards.
+    pub fn recover(&self) -> bool {
+        if self.healthy.load(Ordering::Relaxed) {
+            return true;
+        }
+        let mut provider = match self.provider.try_write() {
+            Ok(provider) => provider,
+            Err(TryLockError::WouldBlock) => return false,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+        };
+
+        // Some providers can not have two instances using the same resources at the same time:
+        // the failed one is dropped first.
+        *provider = None;
+        info!("Re-initialising provider {}.", self.name);
+        match (self.factory)() {
+            Ok(new_provider) => {
+                *provider = Some(new_provider);
+                self.healthy.store(true, Ordering::Relaxed);
+                info!("Provider {} was re-initialised.", self.name);
+                true
+            }
+            Err(err) => {
+                format_error!(
+                    &format!("Failed to re-initialise provider {}", self.name),
+                    err
+                );
+                false
+            }
+        }
+    }
+
+    /// Execute an operation on the provider, marking it as unhealthy if it fails because of the
+    /// underlying hardware.
+    fn call<T>(&self, operation: impl FnOnce(&dyn Provide) -> Result<T>) -> Result<T> {
+        let result = {
+            let provider = self.provider.read().unwrap_or_else(PoisonError::into_inner);
+            let provider = provider
+                .as_ref()
+                .ok_or(ResponseStatus::PsaErrorCommunicationFailure)?;
+            operation(provider.as_ref())
+        };
+        if matches!(result, Err(status) if is_hardware_failure(status))
+            && self.healthy.load(Ordering::Relaxed)
+        {
+            self.confirm_failure();
+        }
+        result
+    }
+
+    /// Probe the provider after one of its operations failed because of the hardware, marking it
+    /// as unhealthy if the probe fails too.
+    ///
+    /// The probe runs alongside the other operations in progress: some of them can be stuck on
+    /// the failed hardware, and waiting for them would block this caller and, behind it, all the
+    /// following ones.
+    fn confirm_failure(&self) {
+        let provider = self.provider.read().unwrap_or_else(PoisonError::into_inner);
+        let provider = match provider.as_ref() {
+            Some(provider) if self.healthy.load(Ordering::Relaxed) => provider,
+            _ => return,
+        };
+
+        match provider.psa_generate_random(psa_generate_random::Operation { size: 1 }) {
+            Err(status) if is_hardware_failure(status) => {
+                if self.healthy.swap(false, Ordering::Relaxed) {
+                    warn!(
+                        "Provider {} failed, it will be re-initialised in the background.",
+                        self.name
+                    );
+                }
+            }
+            _ => info!(
+                "Provider {} still responds, the failure was limited to one operation.",
+                self.name
+            ),
+        }
+    }
+}
+
+/// Check whether the status reports a failure of the hardware behind the provider.
+fn is_hardware_failure(status: ResponseStatus) -> bool {
+    matches!(
+        status,
+        ResponseStatus::PsaErrorCommunicationFailure | ResponseStatus::PsaErrorHardwareFailure
+    )
+}
+
+impl Provide for SupervisedProvider {
+    fn describe(&self) -> Result<(list_providers::ProviderInfo, HashSet<Opcode>)> {
+        self.call(|provider| provider.describe())
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    fn list_providers(&self, op: list_providers::Operation) -> Result<list_providers::Result> {
+        self.call(|provider| provider.list_providers(op))
+    }
+
+    fn list_opcodes(&self, op: list_opcodes::Operation) -> Result<list_opcodes::Result> {
+        self.call(|provider| provider.list_opcodes(op))
+    }
+
+    fn list_authenticators(
+        &self,
+        op: list_authenticators::Operation,
+    ) -> Result<list_authenticators::Result> {
+        self.call(|provider| provider.list_authenticators(op))
+    }
+
+    fn list_keys(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: list_keys::Operation,
+    ) -> Result<list_keys::Result> {
+        self.call(|provider| provider.list_keys(application_identity, op))
+    }
+
+    fn list_clients(&self, op: list_clients::Operation) -> Result<list_clients::Result> {
+        self.call(|provider| provider.list_clients(op))
+    }
+
+    fn delete_client(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: delete_client::Operation,
+    ) -> Result<delete_client::Result> {
+        self.call(|provider| provider.delete_client(application_identity, op))
+    }
+
+    fn ping(&self, op: ping::Operation) -> Result<ping::Result> {
+        self.call(|provider| provider.ping(op))
+    }
+
+    fn psa_generate_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_generate_key::Operation,
+    ) -> Result<psa_generate_key::Result> {
+        self.call(|provider| provider.psa_generate_key(application_identity, op))
+    }
+
+    fn psa_import_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_import_key::Operation,
+    ) -> Result<psa_import_key::Result> {
+        self.call(|provider| provider.psa_import_key(application_identity, op))
+    }
+
+    fn psa_export_public_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_public_key::Operation,
+    ) -> Result<psa_export_public_key::Result> {
+        self.call(|provider| provider.psa_export_public_key(application_identity, op))
+    }
+
+    fn psa_export_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_export_key::Operation,
+    ) -> Result<psa_export_key::Result> {
+        self.call(|provider| provider.psa_export_key(application_identity, op))
+    }
+
+    fn psa_destroy_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_destroy_key::Operation,
+    ) -> Result<psa_destroy_key::Result> {
+        self.call(|provider| provider.psa_destroy_key(application_identity, op))
+    }
+
+    fn psa_sign_hash(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_hash::Operation,
+    ) -> Result<psa_sign_hash::Result> {
+        self.call(|provider| provider.psa_sign_hash(application_identity, op))
+    }
+
+    fn psa_verify_hash(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_verify_hash::Operation,
+    ) -> Result<psa_verify_hash::Result> {
+        self.call(|provider| provider.psa_verify_hash(application_identity, op))
+    }
+
+    fn psa_asymmetric_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_asymmetric_encrypt::Operation,
+    ) -> Result<psa_asymmetric_encrypt::Result> {
+        self.call(|provider| provider.psa_asymmetric_encrypt(application_identity, op))
+    }
+
+    fn psa_asymmetric_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_asymmetric_decrypt::Operation,
+    ) -> Result<psa_asymmetric_decrypt::Result> {
+        self.call(|provider| provider.psa_asymmetric_decrypt(application_identity, op))
+    }
+
+    fn psa_aead_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_encrypt::Operation,
+    ) -> Result<psa_aead_encrypt::Result> {
+        self.call(|provider| provider.psa_aead_encrypt(application_identity, op))
+    }
+
+    fn psa_aead_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_aead_decrypt::Operation,
+    ) -> Result<psa_aead_decrypt::Result> {
+        self.call(|provider| provider.psa_aead_decrypt(application_identity, op))
+    }
+
+    fn psa_hash_compute(
+        &self,
+        op: psa_hash_compute::Operation,
+    ) -> Result<psa_hash_compute::Result> {
+        self.call(|provider| provider.psa_hash_compute(op))
+    }
+
+    fn psa_hash_compare(
+        &self,
+        op: psa_hash_compare::Operation,
+    ) -> Result<psa_hash_compare::Result> {
+        self.call(|provider| provider.psa_hash_compare(op))
+    }
+
+    fn psa_raw_key_agreement(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_raw_key_agreement::Operation,
+    ) -> Result<psa_raw_key_agreement::Result> {
+        self.call(|provider| provider.psa_raw_key_agreement(application_identity, op))
+    }
+
+    fn psa_generate_random(
+        &self,
+        op: psa_generate_random::Operation,
+    ) -> Result<psa_generate_random::Result> {
+        self.call(|provider| provider.psa_generate_random(op))
+    }
+
+    fn psa_cipher_encrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_cipher_encrypt::Operation,
+    ) -> Result<psa_cipher_encrypt::Result> {
+        self.call(|provider| provider.psa_cipher_encrypt(application_identity, op))
+    }
+
+    fn psa_cipher_decrypt(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_cipher_decrypt::Operation,
+    ) -> Result<psa_cipher_decrypt::Result> {
+        self.call(|provider| provider.psa_cipher_decrypt(application_identity, op))
+    }
+
+    fn psa_sign_message(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_sign_message::Operation,
+    ) -> Result<psa_sign_message::Result> {
+        self.call(|provider| provider.psa_sign_message(application_identity, op))
+    }
+
+    fn psa_verify_message(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: psa_verify_message::Operation,
+    ) -> Result<psa_verify_message::Result> {
+        self.call(|provider| provider.psa_verify_message(application_identity, op))
+    }
+
+    fn can_do_crypto(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: can_do_crypto::Operation,
+    ) -> Result<can_do_crypto::Result> {
+        self.call(|provider| provider.can_do_crypto(application_identity, op))
+    }
+
+    fn prepare_key_attestation(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: prepare_key_attestation::Operation,
+    ) -> Result<prepare_key_attestation::Result> {
+        self.call(|provider| provider.prepare_key_attestation(application_identity, op))
+    }
+
+    fn attest_key(
+        &self,
+        application_identity: &ApplicationIdentity,
+        op: attest_key::Operation,
+    ) -> Result<attest_key::Result> {
+        self.call(|provider| provider.attest_key(application_identity, op))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SupervisedProvider;
+    use crate::providers::mock::MockProvider;
+    use crate::providers::Provide;
+    use parsec_interface::operations::{ping, psa_generate_random};
+    use parsec_interface::requests::{ProviderId, ResponseStatus};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Provider whose random number generator fails once `unplugged` is set, and whose pings
+    /// always fail
+    fn unpluggable_provider(unplugged: Arc<AtomicBool>) -> Arc<MockProvider> {
+        Arc::new(
+            MockProvider::new(ProviderId::Pkcs11)
+                .with_ping(|_| Err(ResponseStatus::PsaErrorCommunicationFailure))
+                .with_psa_generate_random(move |op| {
+                    if unplugged.load(Ordering::Relaxed) {
+                        Err(ResponseStatus::PsaErrorCommunicationFailure)
+                    } else {
+                        Ok(psa_generate_random::Result {
+                            random_bytes: vec![0; op.size].into(),
+                        })
+                    }
+                }),
+        )
+    }
+
+    #[test]
+    fn failed_provider_is_reinitialised() {
+        let unplugged = Arc::new(AtomicBool::new(false));
+        let instances = Arc::new(AtomicUsize::new(0));
+        let factory_unplugged = unplugged.clone();
+        let factory_instances = instances.clone();
+        let supervised = SupervisedProvider::new(
+            "unpluggable".to_string(),
+            unpluggable_provider(unplugged.clone()),
+            Box::new(move || {
+                let _ = factory_instances.fetch_add(1, Ordering::Relaxed);
+                if factory_unplugged.load(Ordering::Relaxed) {
+                    anyhow::bail!("still unplugged");
+                }
+                Ok(unpluggable_provider(factory_unplugged.clone()))
+            }),
+        );
+        let generate_random =
+            || supervised.psa_generate_random(psa_generate_random::Operation { size: 4 });
+
+        assert!(generate_random().is_ok());
+        assert!(supervised.is_healthy());
+
+        // The provider still works: the failure of one operation does not make it unhealthy.
+        assert_eq!(
+            supervised.ping(ping::Operation {}).unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+        assert!(supervised.is_healthy());
+
+        unplugged.store(true, Ordering::Relaxed);
+        assert_eq!(
+            generate_random().unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+        assert!(!supervised.is_healthy());
+        assert!(!supervised.recover());
+        assert_eq!(
+            generate_random().unwrap_err(),
+            ResponseStatus::PsaErrorCommunicationFailure
+        );
+
+        unplugged.store(false, Ordering::Relaxed);
+        assert!(supervised.recover());
+        assert!(supervised.is_healthy());
+        assert!(generate_random().is_ok());
+        assert_eq!(instances.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn blocked_operation_does_not_stall_other_callers() {
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let (started_sender, released) = (Mutex::new(started_sender), Mutex::new(released));
+        let supervised = Arc::new(SupervisedProvider::new(
+            "stuck".to_string(),
+            Arc::new(
+                MockProvider::new(ProviderId::Pkcs11)
+                    .with_ping(move |_| {
+                        started_sender.lock().unwrap().send(()).unwrap();
+                        let _ = released.lock().unwrap().recv();
+                        Ok(ping::Result {
+                            wire_protocol_version_maj: 1,
+                            wire_protocol_version_min: 0,
+                        })
+                    })
+                    .with_psa_generate_random(|_| {
+                        Err(ResponseStatus::PsaErrorCommunicationFailure)
+                    }),
+            ),
+            Box::new(|| anyhow::bail!("still unplugged")),
+        ));
+
+        let pinging_supervised = supervised.clone();
+        let pinging = thread::spawn(move || pinging_supervised.ping(ping::Operation {}));
+        started.recv().unwrap();
+
+        // The ping is stuck while holding the provider: the failures of the other operations are
+        // still confirmed and returned.
+        let (results_sender, results) = mpsc::channel();
+        let calling_supervised = supervised.clone();
+        let _ = thread::spawn(move || {
+            for _ in 0..2 {
+                let result = calling_supervised
+                    .psa_generate_random(psa_generate_random::Operation { size: 4 });
+                results_sender.send(result).unwrap();
+            }
+        });
+        for _ in 0..2 {
+            assert_eq!(
+                results
+                    .recv_timeout(Duration::from_secs(10))
+                    .expect("Caller stalled behind a blocked operation")
+                    .unwrap_err(),
+                ResponseStatus::PsaErrorCommunicationFailure
+            );
+        }
+        assert!(!supervised.is_healthy());
+        assert!(!supervised.recover());
+
+        release.send(()).unwrap();
+        assert!(pinging.join().unwrap().is_ok());
+    }
+}
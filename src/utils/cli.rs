@@ -23,3 +23,21 @@ pub struct Opts {
     #[structopt(short, long, default_value = "config.toml")]
     pub config: String,
 }
+
+/// Replay the requests of a capture file, recorded by the Parsec service with the `capture_file`
+/// option, and compare the responses with the recorded ones.
+///
+/// The requests are processed by a service built from the given configuration file, within this
+/// process. Authentication fields are redacted in capture files: requests which used the Unix
+/// peer credentials authenticator are replayed as coming from the user running this tool, other
+/// requests are replayed without authentication. The bodies of operations carrying key material,
+/// plaintexts or random bytes are redacted as well: those requests can not be replayed faithfully.
+#[derive(StructOpt, Debug)]
+pub struct ReplayOpts {
+    /// Sets the configuration file path of the service replaying the requests
+    #[structopt(short, long, default_value = "config.toml")]
+    pub config: String,
+
+    /// Path of the capture file to replay
+    pub capture: String,
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 //! Structures for the Parsec configuration file
 
+use crate::front::listener::Protocol;
 #[cfg(feature = "cryptoauthlib-provider")]
 use crate::providers::cryptoauthlib::Provider as CryptoAuthLibProvider;
 #[cfg(feature = "mbed-crypto-provider")]
@@ -21,8 +22,12 @@ use crate::providers::trusted_service::Provider as TrustedServiceProvider;
 )))]
 use log::error;
 use log::LevelFilter;
-use parsec_interface::requests::ProviderId;
-use serde::Deserialize;
+use num_traits::FromPrimitive;
+use parsec_interface::requests::{AuthType, Opcode, ProviderId};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt;
 use std::io::Error;
 #[cfg(not(all(
     feature = "mbed-crypto-provider",
@@ -37,7 +42,7 @@ use zeroize::Zeroize;
 /// Core settings
 ///
 /// See the config.toml file for a description of each field.
-#[derive(Copy, Clone, Deserialize, Debug)]
+#[derive(Clone, Deserialize, Debug)]
 #[allow(missing_docs)]
 pub struct CoreSettings {
     pub thread_pool_size: Option<usize>,
@@ -49,17 +54,32 @@ pub struct CoreSettings {
     pub allow_root: Option<bool>,
     pub buffer_size_limit: Option<usize>,
     pub allow_deprecated: Option<bool>,
+    pub persistent_connections: Option<bool>,
+    pub connection_idle_timeout: Option<u64>,
+    pub max_requests_per_connection: Option<usize>,
+    pub max_queue_depth: Option<usize>,
+    pub max_concurrent_connections: Option<usize>,
+    pub capture_file: Option<String>,
+    pub auto_provider_selection: Option<bool>,
+    pub provider_recovery_interval: Option<u64>,
+    pub stateless_failover: Option<bool>,
 }
 
 /// Type of the Listener used
-#[derive(Copy, Clone, Deserialize, Debug)]
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
 pub enum ListenerType {
     /// Listener using Unix Domain Socket
     DomainSocket,
+    /// Listener using TCP connections protected with mutual TLS
+    TcpTls,
+    /// Listener using AF_VSOCK sockets, for virtual machine guests
+    Vsock,
+    /// Listener using plain TCP connections, only for the HTTP gateway
+    Tcp,
 }
 
 /// Configuration of the Listener
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Deserialize, Debug, PartialEq, Eq)]
 pub struct ListenerConfig {
     /// Type of the Listener
     pub listener_type: ListenerType,
@@ -67,10 +87,87 @@ pub struct ListenerConfig {
     pub timeout: u64,
     /// Path of the Unix Domain socket
     pub socket_path: Option<String>,
+    /// Permissions of the Unix Domain socket file
+    pub socket_mode: Option<u32>,
+    /// Name of the group owning the Unix Domain socket file
+    pub socket_group: Option<String>,
+    /// Permissions of the directory containing the Unix Domain socket file
+    pub socket_directory_mode: Option<u32>,
+    /// TCP address to listen on, for the TcpTls and Tcp listeners
+    pub address: Option<String>,
+    /// Path of the PEM file containing the server certificate chain, for the TcpTls listener
+    pub server_certificate: Option<String>,
+    /// Path of the PEM file containing the server private key, for the TcpTls listener
+    pub server_private_key: Option<String>,
+    /// Path of the PEM file containing the certificate authorities that client certificates must
+    /// be signed by, for the TcpTls listener
+    pub client_ca_certificates: Option<String>,
+    /// Context identifier (CID) to bind to, for the Vsock listener
+    pub cid: Option<u32>,
+    /// Port to listen on, for the Vsock listener
+    pub port: Option<u32>,
+    /// Authenticators that requests received on this listener can use. All of them are permitted
+    /// if not set.
+    pub authenticators: Option<Vec<AuthenticatorType>>,
+    /// Protocol spoken by the clients of the listener. Defaults to the Parsec wire protocol.
+    pub protocol: Option<Protocol>,
+}
+
+/// Type of an authenticator, as named in the listener configuration
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
+pub enum AuthenticatorType {
+    /// Direct authentication
+    Direct,
+    /// Unix Peer Credentials authentication
+    UnixPeerCredentials,
+    /// JWT-SVID
+    JwtSvid,
+    /// JSON Web Tokens
+    Jwt,
+}
+
+impl From<AuthenticatorType> for AuthType {
+    fn from(authenticator_type: AuthenticatorType) -> Self {
+        match authenticator_type {
+            AuthenticatorType::Direct => AuthType::Direct,
+            AuthenticatorType::UnixPeerCredentials => AuthType::UnixPeerCredentials,
+            AuthenticatorType::JwtSvid => AuthType::JwtSvid,
+            AuthenticatorType::Jwt => AuthType::Jwt,
+        }
+    }
+}
+
+/// Deserialize the listeners, given either as a single `[listener]` table or as an array of
+/// `[[listener]]` tables.
+fn deserialize_listeners<'de, D>(deserializer: D) -> Result<Vec<ListenerConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct ListenersVisitor;
+
+    impl<'de> Visitor<'de> for ListenersVisitor {
+        type Value = Vec<ListenerConfig>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a listener table or an array of listener tables")
+        }
+
+        fn visit_map<M: MapAccess<'de>>(self, map: M) -> Result<Self::Value, M::Error> {
+            Ok(vec![ListenerConfig::deserialize(
+                de::value::MapAccessDeserializer::new(map),
+            )?])
+        }
+
+        fn visit_seq<S: SeqAccess<'de>>(self, seq: S) -> Result<Self::Value, S::Error> {
+            Vec::deserialize(de::value::SeqAccessDeserializer::new(seq))
+        }
+    }
+
+    deserializer.deserialize_any(ListenersVisitor)
 }
 
 /// Authenticator configuration structure
-#[derive(Deserialize, Debug, Zeroize)]
+#[derive(Deserialize, Debug, Zeroize, PartialEq, Eq)]
 #[zeroize(drop)]
 #[serde(tag = "auth_type")]
 pub enum AuthenticatorConfig {
@@ -83,6 +180,10 @@ pub enum AuthenticatorConfig {
     UnixPeerCredentials {
         /// List of service admins
         admins: Option<Vec<Admin>>,
+        /// GIDs of the groups whose members are named after the group instead of their UID
+        group_identities: Option<Vec<u32>>,
+        /// GIDs of the groups whose members are service admins
+        admin_groups: Option<Vec<u32>>,
     },
     /// JWT-SVID
     JwtSvid {
@@ -90,11 +191,73 @@ pub enum AuthenticatorConfig {
         workload_endpoint: String,
         /// List of service admins
         admins: Option<Vec<Admin>>,
+        /// Validate the JWT-SVIDs locally instead of through the Workload API
+        offline_validation: Option<bool>,
+        /// Trust bundles used for offline validation, fetched from the Workload API if not set
+        trust_bundles: Option<Vec<TrustBundleConfig>>,
+        /// Interval, in seconds, between two refreshes of the trust bundles
+        trust_bundle_refresh_interval: Option<u64>,
+    },
+    /// JSON Web Tokens signed by OpenID Connect providers
+    Jwt {
+        /// Trusted issuers of the tokens
+        issuers: Vec<JwtIssuerConfig>,
+        /// Accepted values of the `aud` claim, one of which must be in the tokens
+        audience: Vec<String>,
+        /// Claim containing the application name, `sub` by default
+        name_claim: Option<String>,
+        /// List of service admins
+        admins: Option<Vec<Admin>>,
+    },
+    /// Identity of the executable run by the client, with Unix peer credentials
+    PeerExecutable {
+        /// List of service admins
+        admins: Option<Vec<Admin>>,
+        /// Whether the SHA-256 digest of the executable is part of the application name
+        hash_executable: Option<bool>,
+    },
+    /// Control group, systemd unit or container of the client, with Unix peer credentials
+    Cgroup {
+        /// List of service admins
+        admins: Option<Vec<Admin>>,
+        /// Patterns mapping the control groups to application names, tried in order
+        cgroup_patterns: Vec<CgroupPattern>,
     },
 }
 
+/// Mapping of the control groups matching a pattern to an application name
+#[derive(Deserialize, Debug, Zeroize, Clone, PartialEq, Eq)]
+#[zeroize(drop)]
+pub struct CgroupPattern {
+    /// Regular expression matched against the control group path
+    pub pattern: String,
+    /// Application name, in which `$name` or `${name}` is replaced by the group of the pattern with
+    /// that name or number
+    pub name: String,
+}
+
+/// JWKS trust bundle of a SPIFFE trust domain, stored in a file
+#[derive(Deserialize, Debug, Zeroize, Clone, PartialEq, Eq)]
+#[zeroize(drop)]
+pub struct TrustBundleConfig {
+    /// Name of the trust domain
+    pub trust_domain: String,
+    /// Path of the JWKS document containing the JWT authorities of the trust domain
+    pub path: String,
+}
+
+/// Trusted issuer of JSON Web Tokens
+#[derive(Deserialize, Debug, Zeroize, Clone, PartialEq, Eq)]
+#[zeroize(drop)]
+pub struct JwtIssuerConfig {
+    /// Value of the `iss` claim in the tokens of the issuer
+    pub issuer: String,
+    /// Path of the JWKS document containing the public keys of the issuer
+    pub jwks_path: String,
+}
+
 /// Structure defining the properties of a service admin
-#[derive(Deserialize, Debug, Zeroize, Clone)]
+#[derive(Deserialize, Debug, Zeroize, Clone, PartialEq, Eq)]
 #[zeroize(drop)]
 pub struct Admin {
     name: String,
@@ -108,7 +271,7 @@ impl Admin {
 }
 
 /// Type of the KeyInfoManager
-#[derive(Copy, Clone, Deserialize, Debug)]
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq, Eq)]
 pub enum KeyInfoManagerType {
     /// KeyInfoManager storing the mappings on disk
     OnDisk,
@@ -117,7 +280,7 @@ pub enum KeyInfoManagerType {
 }
 
 /// KeyInfoManager configuration
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, PartialEq, Eq)]
 pub struct KeyInfoManagerConfig {
     /// Name of the KeyInfoManager
     pub name: String,
@@ -134,7 +297,7 @@ pub struct KeyInfoManagerConfig {
 /// to the one described in the Internally Tagged Enum representation
 /// where "provider_type" is the tag field. For details see:
 /// https://serde.rs/enum-representations.html
-#[derive(Deserialize, Debug, Zeroize)]
+#[derive(Clone, Deserialize, Debug, Zeroize, PartialEq, Eq)]
 #[zeroize(drop)]
 #[serde(tag = "provider_type")]
 pub enum ProviderConfig {
@@ -294,8 +457,202 @@ impl ProviderConfig {
 #[allow(missing_docs)]
 pub struct ServiceConfig {
     pub core_settings: CoreSettings,
-    pub listener: ListenerConfig,
+    #[serde(deserialize_with = "deserialize_listeners")]
+    pub listener: Vec<ListenerConfig>,
     pub authenticator: AuthenticatorConfig,
     pub key_manager: Option<Vec<KeyInfoManagerConfig>>,
     pub provider: Option<Vec<ProviderConfig>>,
+    pub rate_limits: Option<RateLimitsConfig>,
+    pub deadlines: Option<DeadlinesConfig>,
+}
+
+/// Values given per opcode
+///
+/// In the configuration file, they are written as tables whose keys are the names of the opcodes,
+/// for example `PsaGenerateKey`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PerOpcode<T>(pub HashMap<Opcode, T>);
+
+// Deriving Default would needlessly require T to implement it.
+impl<T> Default for PerOpcode<T> {
+    fn default() -> Self {
+        PerOpcode(HashMap::new())
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PerOpcode<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut values = HashMap::new();
+        for (name, value) in HashMap::<String, T>::deserialize(deserializer)? {
+            let opcode = opcode_from_name(&name)
+                .ok_or_else(|| de::Error::custom(format!("unknown opcode {}", name)))?;
+            let _ = values.insert(opcode, value);
+        }
+
+        Ok(PerOpcode(values))
+    }
+}
+
+/// Find the opcode with the given name.
+fn opcode_from_name(name: &str) -> Option<Opcode> {
+    // Opcodes are small numbers: they are all below 0x100.
+    (0..0x100)
+        .filter_map(Opcode::from_u32)
+        .find(|opcode| format!("{:?}", opcode) == name)
+}
+
+/// Token bucket limiting the rate of requests
+#[derive(Copy, Clone, Deserialize, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Number of requests allowed per second, on average
+    pub rate: f64,
+    /// Number of requests that can be sent at once, after a period without any
+    pub burst: u32,
+}
+
+/// Rate limits applied to the requests of applications, per opcode
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct RateLimitsConfig {
+    /// Limits applied to all applications
+    #[serde(default)]
+    pub default: PerOpcode<RateLimit>,
+    /// Limits applied to some applications instead of the default ones, indexed by application
+    /// name
+    #[serde(default)]
+    pub applications: HashMap<String, PerOpcode<RateLimit>>,
+}
+
+/// Execution deadlines of the operations, in milliseconds
+#[derive(Clone, Deserialize, Debug, Default)]
+pub struct DeadlinesConfig {
+    /// Deadline of the operations which do not have one of their own
+    pub default: Option<u64>,
+    /// Deadlines of specific operations
+    #[serde(default)]
+    pub operations: PerOpcode<u64>,
+    /// Number of operations of a provider which can run past their deadline at the same time
+    pub max_overdue_operations: Option<usize>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AuthenticatorType, RateLimit, ServiceConfig};
+    use parsec_interface::requests::Opcode;
+
+    const CORE_AND_AUTHENTICATOR: &str = r#"
+        [core_settings]
+        [authenticator]
+        auth_type = "Direct"
+    "#;
+
+    #[test]
+    fn single_listener_table() {
+        let config: ServiceConfig = toml::from_str(&format!(
+            r#"{}
+            [listener]
+            listener_type = "DomainSocket"
+            timeout = 200
+            "#,
+            CORE_AND_AUTHENTICATOR
+        ))
+        .unwrap();
+        assert_eq!(config.listener.len(), 1);
+        assert!(config.listener[0].authenticators.is_none());
+    }
+
+    #[test]
+    fn array_of_listener_tables() {
+        let config: ServiceConfig = toml::from_str(&format!(
+            r#"{}
+            [[listener]]
+            listener_type = "DomainSocket"
+            timeout = 200
+
+            [[listener]]
+            listener_type = "DomainSocket"
+            timeout = 100
+            socket_path = "/tmp/restricted.sock"
+            authenticators = ["UnixPeerCredentials"]
+            "#,
+            CORE_AND_AUTHENTICATOR
+        ))
+        .unwrap();
+        assert_eq!(config.listener.len(), 2);
+        assert_eq!(config.listener[1].timeout, 100);
+        assert_eq!(
+            config.listener[1].authenticators,
+            Some(vec![AuthenticatorType::UnixPeerCredentials])
+        );
+    }
+
+    #[test]
+    fn rate_limits_per_opcode() {
+        let config: ServiceConfig = toml::from_str(&format!(
+            r#"{}
+            [listener]
+            listener_type = "DomainSocket"
+            timeout = 200
+
+            [rate_limits.default]
+            PsaGenerateKey = {{ rate = 1.0, burst = 5 }}
+
+            [rate_limits.applications."app-1"]
+            PsaGenerateRandom = {{ rate = 0.5, burst = 1 }}
+            "#,
+            CORE_AND_AUTHENTICATOR
+        ))
+        .unwrap();
+        let rate_limits = config.rate_limits.unwrap();
+        assert_eq!(
+            rate_limits.default.0[&Opcode::PsaGenerateKey],
+            RateLimit {
+                rate: 1.0,
+                burst: 5
+            }
+        );
+        assert_eq!(
+            rate_limits.applications["app-1"].0[&Opcode::PsaGenerateRandom].burst,
+            1
+        );
+
+        let err = toml::from_str::<ServiceConfig>(&format!(
+            r#"{}
+            [listener]
+            listener_type = "DomainSocket"
+            timeout = 200
+
+            [rate_limits.default]
+            PsaGenerateKeys = {{ rate = 1.0, burst = 5 }}
+            "#,
+            CORE_AND_AUTHENTICATOR
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown opcode PsaGenerateKeys"));
+    }
+
+    #[test]
+    fn deadlines_per_opcode() {
+        let config: ServiceConfig = toml::from_str(&format!(
+            r#"{}
+            [listener]
+            listener_type = "DomainSocket"
+            timeout = 200
+
+            [deadlines]
+            default = 5000
+
+            [deadlines.operations]
+            PsaGenerateKey = 30000
+            "#,
+            CORE_AND_AUTHENTICATOR
+        ))
+        .unwrap();
+        let deadlines = config.deadlines.unwrap();
+        assert_eq!(deadlines.default, Some(5000));
+        assert_eq!(deadlines.operations.0[&Opcode::PsaGenerateKey], 30000);
+        assert!(!deadlines
+            .operations
+            .0
+            .contains_key(&Opcode::PsaGenerateRandom));
+    }
 }
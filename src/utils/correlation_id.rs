@@ -0,0 +1,90 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Request correlation IDs
+//!
+//! Each request gets an ID when the front end starts processing it. The ID is kept in a
+//! thread-local variable while the request is processed, so that all the log records made in the
+//! meantime, by any component, can be tagged with it.
+use std::cell::Cell;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT_ID: Cell<Option<CorrelationId>> = const { Cell::new(None) };
+}
+
+/// Identifier of a request, unique within the lifetime of the service
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CorrelationId(u64);
+
+impl CorrelationId {
+    /// Allocate a new correlation ID.
+    pub fn new() -> Self {
+        CorrelationId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Correlation ID of the request processed by the current thread, if any.
+    pub fn current() -> Option<Self> {
+        CURRENT_ID.with(Cell::get)
+    }
+}
+
+impl Default for CorrelationId {
+    fn default() -> Self {
+        CorrelationId::new()
+    }
+}
+
+impl fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "req-{:08x}", self.0)
+    }
+}
+
+/// Sets the correlation ID of the current thread, until dropped
+///
+/// The previous correlation ID of the thread, if any, is restored when the scope is dropped.
+#[derive(Debug)]
+pub struct CorrelationScope {
+    previous: Option<CorrelationId>,
+}
+
+impl CorrelationScope {
+    /// Tag the work done by the current thread with `id`.
+    pub fn enter(id: CorrelationId) -> Self {
+        CorrelationScope {
+            previous: CURRENT_ID.with(|current| current.replace(Some(id))),
+        }
+    }
+}
+
+impl Drop for CorrelationScope {
+    fn drop(&mut self) {
+        CURRENT_ID.with(|current| current.set(self.previous));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CorrelationId, CorrelationScope};
+
+    #[test]
+    fn scopes_are_nested() {
+        let first = CorrelationId::new();
+        let second = CorrelationId::new();
+        assert_ne!(first, second);
+
+        {
+            let _scope = CorrelationScope::enter(first);
+            assert_eq!(CorrelationId::current(), Some(first));
+            {
+                let _scope = CorrelationScope::enter(second);
+                assert_eq!(CorrelationId::current(), Some(second));
+            }
+            assert_eq!(CorrelationId::current(), Some(first));
+        }
+        assert_eq!(CorrelationId::current(), None);
+    }
+}
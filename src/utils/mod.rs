@@ -3,7 +3,9 @@
 //! Service utilities
 pub mod cli;
 pub mod config;
+pub mod correlation_id;
 mod global_config;
+pub mod process;
 mod service_builder;
 #[cfg(all(
     feature = "mbed-crypto-provider",
@@ -17,4 +19,4 @@ mod service_builder;
 mod tests;
 
 pub use global_config::GlobalConfig;
-pub use service_builder::ServiceBuilder;
+pub use service_builder::{ServiceBuilder, ServiceComponents};
@@ -0,0 +1,53 @@
+// Copyright 2023 Contributors to the Parsec project.
+// SPDX-License-Identifier: Apache-2.0
+//! Information about client processes
+//!
+//! Authenticators identifying clients by their process read its properties from `/proc/<pid>`. A
+//! PID can be reused by a new process as soon as the client exits: the start time of the process,
+//! recorded when the connection is accepted, tells whether the PID still designates the client.
+use std::io::{Error, ErrorKind, Result};
+
+/// Start time of the process, in clock ticks after the system boot.
+pub fn start_time(pid: i32) -> Result<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    parse_start_time(&stat)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "invalid /proc/<pid>/stat format"))
+}
+
+/// Check that the process with the given PID is still the one which started at `start_time`.
+pub fn check_start_time(pid: i32, start_time: u64) -> Result<()> {
+    if self::start_time(pid)? == start_time {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("process {} exited and its PID was reused", pid),
+        ))
+    }
+}
+
+/// Parse the start time, the 22nd field, of the content of a `/proc/<pid>/stat` file.
+fn parse_start_time(stat: &str) -> Option<u64> {
+    // The second field is the command name, between parentheses, which can contain anything
+    // including spaces and parentheses: the fields are counted from the last closing parenthesis.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_start_time, parse_start_time, start_time};
+
+    #[test]
+    fn process_start_time() {
+        let stat = "4242 (a (weird) name) S 1 4242 4242 0 -1 4194560 1047 0 0 0 2 1 0 0 20 0 1 0 \
+                    123456 4370432 378 18446744073709551615 1 1 0 0 0 0 0 4096 0 0 0 0 17 3 0 0";
+        assert_eq!(parse_start_time(stat), Some(123456));
+        assert_eq!(parse_start_time("4242 (name) S 1"), None);
+
+        let pid = std::process::id() as i32;
+        let own_start_time = start_time(pid).unwrap();
+        check_start_time(pid, own_start_time).unwrap();
+        let _ = check_start_time(pid, own_start_time + 1).unwrap_err();
+    }
+}
@@ -12,30 +12,50 @@ use crate::back::{
 };
 use crate::front::{
     domain_socket::DomainSocketListenerBuilder, front_end::FrontEndHandler,
-    front_end::FrontEndHandlerBuilder, listener::Listen,
+    front_end::FrontEndHandlerBuilder, listener::Listen, listener::Protocol,
+    listener::RestrictedListener, rate_limiter::RateLimiter, recorder::Recorder,
 };
 use crate::key_info_managers::KeyInfoManagerFactory;
-use crate::providers::{core::ProviderBuilder as CoreProviderBuilder, Provide};
+use crate::providers::core::{Provider as CoreProvider, ProviderBuilder as CoreProviderBuilder};
+use crate::providers::supervisor::{ProviderFactory, SupervisedProvider};
+use crate::providers::Provide;
 use crate::utils::config::{
-    AuthenticatorConfig, KeyInfoManagerConfig, ListenerConfig, ListenerType, ProviderConfig,
-    ServiceConfig,
+    AuthenticatorConfig, DeadlinesConfig, KeyInfoManagerConfig, ListenerConfig, ListenerType,
+    ProviderConfig, ServiceConfig,
 };
 use anyhow::Result;
-use log::{error, warn};
+use derivative::Derivative;
+use log::{error, info, warn};
 use parsec_interface::operations_protobuf::ProtobufConverter;
 use parsec_interface::requests::AuthType;
 use parsec_interface::requests::{BodyType, ProviderId};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::io::{Error, ErrorKind};
+use std::mem;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use std::time::Duration;
 use threadpool::{Builder as ThreadPoolBuilder, ThreadPool};
 
+#[cfg(feature = "http-gateway")]
+use crate::front::http_gateway::{HttpListener, PlainTcpListenerBuilder};
+#[cfg(feature = "tls-listener")]
+use crate::front::tcp_tls::TcpTlsListenerBuilder;
+#[cfg(all(feature = "vsock-listener", target_os = "linux"))]
+use crate::front::vsock::VsockListenerBuilder;
+
+#[cfg(feature = "cgroup-authenticator")]
+use crate::authenticators::cgroup_authenticator::CgroupAuthenticator;
 #[cfg(feature = "direct-authenticator")]
 use crate::authenticators::direct_authenticator::DirectAuthenticator;
+#[cfg(feature = "jwt-authenticator")]
+use crate::authenticators::jwt_authenticator::JwtAuthenticator;
 #[cfg(feature = "jwt-svid-authenticator")]
 use crate::authenticators::jwt_svid_authenticator::JwtSvidAuthenticator;
+#[cfg(feature = "peer-executable-authenticator")]
+use crate::authenticators::peer_executable_authenticator::PeerExecutableAuthenticator;
 #[cfg(feature = "unix-peer-credentials-authenticator")]
 use crate::authenticators::unix_peer_credentials_authenticator::UnixPeerCredentialsAuthenticator;
 
@@ -70,15 +90,6 @@ use crate::providers::trusted_service::Provider as TrustedServiceProvider;
 ))]
 use crate::providers::ProviderIdentity;
 
-#[cfg(any(
-    feature = "mbed-crypto-provider",
-    feature = "pkcs11-provider",
-    feature = "tpm-provider",
-    feature = "cryptoauthlib-provider",
-    feature = "trusted-service-provider"
-))]
-use log::info;
-
 const WIRE_PROTOCOL_VERSION_MINOR: u8 = 0;
 const WIRE_PROTOCOL_VERSION_MAJOR: u8 = 1;
 
@@ -88,8 +99,18 @@ const DEFAULT_BODY_LEN_LIMIT: usize = 1 << 20;
 /// Default value for the limit on the buffer size for response (in bytes) - equal to 1MB
 pub const DEFAULT_BUFFER_SIZE_LIMIT: usize = 1 << 20;
 
+/// Default time after which an idle persistent connection is closed (in milliseconds)
+const DEFAULT_CONNECTION_IDLE_TIMEOUT: u64 = 5000;
+
+/// Default limit on the number of requests served on a persistent connection
+const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+
+/// Default interval between two refreshes of the JWT trust bundles (in seconds)
+#[cfg(feature = "jwt-svid-authenticator")]
+const DEFAULT_TRUST_BUNDLE_REFRESH_INTERVAL: u64 = 300;
+
 type Provider = Arc<dyn Provide + Send + Sync>;
-type Authenticator = Box<dyn Authenticate + Send + Sync>;
+type Authenticator = Arc<dyn Authenticate + Send + Sync>;
 
 /// Service component builder and assembler
 ///
@@ -109,6 +130,19 @@ impl ServiceBuilder {
     /// requested for a certain provider does not exist) or if required fields are missing, an error of kind
     /// `InvalidData` is returned with a string describing the cause more accurately.
     pub fn build_service(config: &ServiceConfig) -> Result<FrontEndHandler> {
+        Self::build_service_reusing(config, &mut ServiceComponents::default())
+    }
+
+    /// Assemble a service like `build_service` does, reusing the authenticators and providers
+    /// kept in `components` instead of creating them again. The components newly created are
+    /// added to `components` so that they can be reused by the next call.
+    ///
+    /// `ServiceComponents::retain_unchanged` must be called first, with the configuration the
+    /// components were created from, to drop the ones that can not be reused.
+    pub fn build_service_reusing(
+        config: &ServiceConfig,
+        components: &mut ServiceComponents,
+    ) -> Result<FrontEndHandler> {
         GlobalConfigBuilder::new()
             .with_log_error_details(config.core_settings.log_error_details.unwrap_or(false))
             .with_buffer_size_limit(
@@ -120,20 +154,38 @@ impl ServiceBuilder {
             .with_allow_deprecated(config.core_settings.allow_deprecated.unwrap_or(false))
             .build();
 
-        let authenticators = build_authenticators(&config.authenticator)?;
+        if components.authenticators.is_empty() {
+            components.authenticators = build_authenticators(&config.authenticator)?;
 
-        if authenticators[0].0 == AuthType::Direct {
-            warn!("Direct authenticator has been set as the default one. It is only secure under specific requirements. Please make sure to read the Recommendations on a Secure Parsec Deployment at https://parallaxsecond.github.io/parsec-book/parsec_security/secure_deployment.html");
+            if components.authenticators[0].0 == AuthType::Direct {
+                warn!("Direct authenticator has been set as the default one. It is only secure under specific requirements. Please make sure to read the Recommendations on a Secure Parsec Deployment at https://parallaxsecond.github.io/parsec-book/parsec_security/secure_deployment.html");
+            }
         }
+        let authenticators = components.authenticators.clone();
 
-        let key_info_manager_builders = get_key_info_manager_builders(
-            config.key_manager.as_ref().unwrap_or(&Vec::new()),
-            authenticators[0].0,
-        )?;
+        let provider_configs = config.provider.as_deref().unwrap_or(&[]);
+        // Key info managers are only needed to create new providers.
+        let key_info_manager_builders = if provider_configs.iter().any(|provider_config| {
+            provider_config
+                .provider_name()
+                .map_or(true, |name| !components.providers.contains_key(&name))
+        }) {
+            get_key_info_manager_builders(
+                config.key_manager.as_ref().unwrap_or(&Vec::new()),
+                authenticators[0].0,
+            )?
+        } else {
+            HashMap::new()
+        };
 
         let providers = build_providers(
-            config.provider.as_ref().unwrap_or(&Vec::new()),
+            provider_configs,
             key_info_manager_builders,
+            config
+                .core_settings
+                .provider_recovery_interval
+                .map(Duration::from_millis),
+            &mut components.providers,
         )?;
 
         if providers.is_empty() {
@@ -141,13 +193,29 @@ impl ServiceBuilder {
             return Err(Error::new(ErrorKind::InvalidData, "need one provider").into());
         }
 
-        let backend_handlers = build_backend_handlers(providers, &authenticators)?;
+        let backend_handlers = build_backend_handlers(
+            providers,
+            &authenticators,
+            &config.deadlines.clone().unwrap_or_default(),
+        )?;
 
-        let dispatcher = DispatcherBuilder::new()
-            .with_backends(backend_handlers)
-            .build()?;
+        // Providers are added in the order of the configuration, which is their priority order.
+        let mut dispatcher_builder = DispatcherBuilder::new()
+            .with_automatic_selection(
+                config
+                    .core_settings
+                    .auto_provider_selection
+                    .unwrap_or(false),
+            )
+            .with_failover(config.core_settings.stateless_failover.unwrap_or(false));
+        for (provider_name, backend_handler) in backend_handlers {
+            dispatcher_builder =
+                dispatcher_builder.with_named_backend(provider_name, backend_handler);
+        }
+        let dispatcher = dispatcher_builder.build()?;
 
-        let mut front_end_handler_builder = FrontEndHandlerBuilder::new();
+        let mut front_end_handler_builder = FrontEndHandlerBuilder::new()
+            .with_rejected_connections(components.rejected_connections.clone());
         for (auth_type, authenticator) in authenticators {
             front_end_handler_builder =
                 front_end_handler_builder.with_authenticator(auth_type, authenticator);
@@ -160,20 +228,167 @@ impl ServiceBuilder {
                     .body_len_limit
                     .unwrap_or(DEFAULT_BODY_LEN_LIMIT),
             );
+        if config.core_settings.persistent_connections.unwrap_or(false) {
+            let max_requests_per_connection = config
+                .core_settings
+                .max_requests_per_connection
+                .unwrap_or(DEFAULT_MAX_REQUESTS_PER_CONNECTION);
+            if max_requests_per_connection == 0 {
+                error!("The maximum number of requests per connection must be at least 1.");
+                return Err(
+                    Error::new(ErrorKind::InvalidData, "max_requests_per_connection is 0").into(),
+                );
+            }
+            front_end_handler_builder = front_end_handler_builder.with_persistent_connections(
+                Duration::from_millis(
+                    config
+                        .core_settings
+                        .connection_idle_timeout
+                        .unwrap_or(DEFAULT_CONNECTION_IDLE_TIMEOUT),
+                ),
+                max_requests_per_connection,
+            );
+        }
+
+        if let Some(rate_limits) = &config.rate_limits {
+            front_end_handler_builder =
+                front_end_handler_builder.with_rate_limiter(RateLimiter::new(rate_limits)?);
+        }
+
+        if let Some(capture_file) = &config.core_settings.capture_file {
+            warn!("Requests and responses are recorded into {}.", capture_file);
+            front_end_handler_builder =
+                front_end_handler_builder.with_recorder(Recorder::new(Path::new(capture_file))?);
+        }
 
         Ok(front_end_handler_builder.build()?)
     }
 
+    /// Construct all the service IPC front components and return ownership to them.
+    pub fn start_listeners(configs: &[ListenerConfig]) -> Result<Vec<Box<dyn Listen>>> {
+        Self::restart_listeners(&[], Vec::new(), configs)
+    }
+
+    /// Construct the service IPC front components for a new configuration. The listeners of the
+    /// previous configuration are kept if their configuration did not change, so that their
+    /// sockets stay open; the others are closed before the new ones are started.
+    pub fn restart_listeners(
+        old_configs: &[ListenerConfig],
+        old_listeners: Vec<Box<dyn Listen>>,
+        configs: &[ListenerConfig],
+    ) -> Result<Vec<Box<dyn Listen>>> {
+        if configs.is_empty() {
+            error!("Parsec needs at least one listener to start.");
+            return Err(Error::new(ErrorKind::InvalidData, "need one listener").into());
+        }
+
+        let mut old_listeners: Vec<(&ListenerConfig, Box<dyn Listen>)> =
+            old_configs.iter().zip(old_listeners).collect();
+        let mut kept_listeners = Vec::new();
+        for config in configs {
+            kept_listeners.push(
+                old_listeners
+                    .iter()
+                    .position(|(old_config, _)| *old_config == config)
+                    .map(|index| old_listeners.remove(index).1),
+            );
+        }
+        // Close the listeners that are not kept before binding new ones to the same addresses.
+        drop(old_listeners);
+
+        configs
+            .iter()
+            .zip(kept_listeners)
+            .map(|(config, kept_listener)| match kept_listener {
+                Some(listener) => Ok(listener),
+                None => Self::start_listener(config.clone()),
+            })
+            .collect()
+    }
+
     /// Construct the service IPC front component and return ownership to it.
+    ///
+    /// If the configuration restricts the authenticators that can be used on the listener, the
+    /// connections it accepts are marked with the permitted authentication types.
     pub fn start_listener(config: ListenerConfig) -> Result<Box<dyn Listen>> {
-        let listener = match config.listener_type {
-            ListenerType::DomainSocket => DomainSocketListenerBuilder::new()
-                .with_timeout(Duration::from_millis(config.timeout))
-                .with_socket_path(config.socket_path.map(|s| s.into()))
-                .build(),
-        }?;
-
-        Ok(Box::new(listener))
+        let permitted_authenticators = config.authenticators.clone();
+        let protocol = config.protocol.unwrap_or(Protocol::Parsec);
+        let listener: Box<dyn Listen> = match config.listener_type {
+            ListenerType::DomainSocket => Box::new(
+                DomainSocketListenerBuilder::new()
+                    .with_timeout(Duration::from_millis(config.timeout))
+                    .with_socket_path(config.socket_path.map(|s| s.into()))
+                    .with_socket_mode(config.socket_mode)
+                    .with_socket_group(config.socket_group)
+                    .with_socket_directory_mode(config.socket_directory_mode)
+                    .build()?,
+            ),
+            #[cfg(feature = "tls-listener")]
+            ListenerType::TcpTls => Box::new(
+                TcpTlsListenerBuilder::new()
+                    .with_timeout(Duration::from_millis(config.timeout))
+                    .with_address(config.address)
+                    .with_server_certificate(config.server_certificate.map(|s| s.into()))
+                    .with_server_private_key(config.server_private_key.map(|s| s.into()))
+                    .with_client_ca_certificates(config.client_ca_certificates.map(|s| s.into()))
+                    .build()?,
+            ),
+            #[cfg(not(feature = "tls-listener"))]
+            ListenerType::TcpTls => {
+                error!("The TcpTls listener chosen in the configuration was not compiled in Parsec binary.");
+                return Err(Error::new(ErrorKind::InvalidData, "listener not compiled").into());
+            }
+            #[cfg(all(feature = "vsock-listener", target_os = "linux"))]
+            ListenerType::Vsock => Box::new(
+                VsockListenerBuilder::new()
+                    .with_timeout(Duration::from_millis(config.timeout))
+                    .with_cid(config.cid)
+                    .with_port(config.port)
+                    .build()?,
+            ),
+            #[cfg(not(all(feature = "vsock-listener", target_os = "linux")))]
+            ListenerType::Vsock => {
+                error!("The Vsock listener chosen in the configuration was not compiled in Parsec binary.");
+                return Err(Error::new(ErrorKind::InvalidData, "listener not compiled").into());
+            }
+            ListenerType::Tcp if protocol != Protocol::Http => {
+                error!("The Tcp listener can only be used with the Http protocol.");
+                return Err(
+                    Error::new(ErrorKind::InvalidData, "plain TCP listener for Parsec").into(),
+                );
+            }
+            #[cfg(feature = "http-gateway")]
+            ListenerType::Tcp => Box::new(
+                PlainTcpListenerBuilder::new()
+                    .with_timeout(Duration::from_millis(config.timeout))
+                    .with_address(config.address)
+                    .build()?,
+            ),
+            #[cfg(not(feature = "http-gateway"))]
+            ListenerType::Tcp => {
+                error!("The Tcp listener chosen in the configuration was not compiled in Parsec binary.");
+                return Err(Error::new(ErrorKind::InvalidData, "listener not compiled").into());
+            }
+        };
+
+        let listener: Box<dyn Listen> = match protocol {
+            Protocol::Parsec => listener,
+            #[cfg(feature = "http-gateway")]
+            Protocol::Http => Box::new(HttpListener::new(listener)),
+            #[cfg(not(feature = "http-gateway"))]
+            Protocol::Http => {
+                error!("The HTTP gateway chosen in the configuration was not compiled in Parsec binary.");
+                return Err(Error::new(ErrorKind::InvalidData, "HTTP gateway not compiled").into());
+            }
+        };
+
+        match permitted_authenticators {
+            Some(authenticators) => Ok(Box::new(RestrictedListener::new(
+                listener,
+                authenticators.into_iter().map(AuthType::from).collect(),
+            ))),
+            None => Ok(listener),
+        }
     }
 
     /// Construct the thread pool that will be used to process all service requests.
@@ -186,11 +401,88 @@ impl ServiceBuilder {
     }
 }
 
+/// Authenticators and providers of a running service
+///
+/// They are kept alive across configuration reloads as long as their configuration does not
+/// change, which avoids tearing down and initialising again providers holding hardware sessions.
+#[derive(Default, Derivative)]
+#[derivative(Debug)]
+pub struct ServiceComponents {
+    #[derivative(Debug = "ignore")]
+    authenticators: Vec<(AuthType, Authenticator)>,
+    /// Providers indexed by their name
+    #[derivative(Debug = "ignore")]
+    providers: HashMap<String, (ProviderId, Provider)>,
+    /// Number of connections rejected because the service was overloaded, since it started
+    rejected_connections: Arc<AtomicUsize>,
+}
+
+impl ServiceComponents {
+    /// Drop the components which can not be reused when the configuration changes from
+    /// `old_config` to `new_config`.
+    ///
+    /// Returns `true` if at least one provider was dropped. In that case, the service built from
+    /// `old_config` must be completely dropped, after the requests it is processing are complete,
+    /// before building the new one: only one instance of each provider can exist at a time.
+    pub fn retain_unchanged(
+        &mut self,
+        old_config: &ServiceConfig,
+        new_config: &ServiceConfig,
+    ) -> bool {
+        if old_config.authenticator != new_config.authenticator {
+            self.authenticators.clear();
+        }
+        // The key info managers used by providers depend on the default authenticator type.
+        let authenticator_type_changed = mem::discriminant(&old_config.authenticator)
+            != mem::discriminant(&new_config.authenticator);
+
+        let providers_before = self.providers.len();
+        self.providers.retain(|name, _| {
+            !authenticator_type_changed
+                && match (
+                    provider_config(old_config, name),
+                    provider_config(new_config, name),
+                ) {
+                    (Some(old_provider), Some(new_provider)) => {
+                        old_provider == new_provider
+                            && key_info_manager_config(old_config, old_provider)
+                                == key_info_manager_config(new_config, new_provider)
+                    }
+                    _ => false,
+                }
+        });
+
+        self.providers.len() != providers_before
+    }
+}
+
+fn provider_config<'a>(config: &'a ServiceConfig, name: &str) -> Option<&'a ProviderConfig> {
+    config
+        .provider
+        .as_deref()
+        .unwrap_or(&[])
+        .iter()
+        .find(|provider_config| matches!(provider_config.provider_name(), Ok(provider_name) if provider_name == name))
+}
+
+fn key_info_manager_config<'a>(
+    config: &'a ServiceConfig,
+    provider_config: &ProviderConfig,
+) -> Option<&'a KeyInfoManagerConfig> {
+    config
+        .key_manager
+        .as_deref()
+        .unwrap_or(&[])
+        .iter()
+        .find(|key_manager_config| key_manager_config.name == *provider_config.key_info_manager())
+}
+
 fn build_backend_handlers(
-    mut providers: Vec<(ProviderId, Provider)>,
+    mut providers: Vec<(String, ProviderId, Provider)>,
     authenticators: &[(AuthType, Authenticator)],
-) -> Result<HashMap<ProviderId, BackEndHandler>> {
-    let mut map = HashMap::new();
+    deadlines: &DeadlinesConfig,
+) -> Result<Vec<(String, BackEndHandler)>> {
+    let mut backend_handlers = Vec::new();
 
     let mut core_provider_builder = CoreProviderBuilder::new()
         .with_wire_protocol_version(WIRE_PROTOCOL_VERSION_MINOR, WIRE_PROTOCOL_VERSION_MAJOR);
@@ -202,38 +494,52 @@ fn build_backend_handlers(
         core_provider_builder = core_provider_builder.with_authenticator_info(authenticator_info);
     }
 
-    for (provider_id, provider) in providers.drain(..) {
-        core_provider_builder = core_provider_builder.with_provider(provider.clone());
+    for (provider_name, provider_id, provider) in providers.drain(..) {
+        core_provider_builder =
+            core_provider_builder.with_named_provider(provider_name.clone(), provider.clone());
 
-        let backend_handler = BackEndHandlerBuilder::new()
+        let backend_handler = with_converters(BackEndHandlerBuilder::new())
             .with_provider(provider)
-            .with_converter(Box::from(ProtobufConverter {}))
             .with_provider_id(provider_id)
-            .with_content_type(BodyType::Protobuf)
-            .with_accept_type(BodyType::Protobuf)
+            .with_deadlines(deadlines)
             .build()?;
-        let _ = map.insert(provider_id, backend_handler);
+        backend_handlers.push((provider_name, backend_handler));
     }
 
-    let core_provider_backend = BackEndHandlerBuilder::new()
+    let core_provider_backend = with_converters(BackEndHandlerBuilder::new())
         .with_provider(Arc::new(core_provider_builder.build()?))
-        .with_converter(Box::from(ProtobufConverter {}))
         .with_provider_id(ProviderId::Core)
-        .with_content_type(BodyType::Protobuf)
-        .with_accept_type(BodyType::Protobuf)
+        .with_deadlines(deadlines)
         .build()?;
 
-    let _ = map.insert(ProviderId::Core, core_provider_backend);
+    backend_handlers.push((
+        CoreProvider::DEFAULT_PROVIDER_NAME.to_string(),
+        core_provider_backend,
+    ));
 
-    Ok(map)
+    Ok(backend_handlers)
+}
+
+/// Add the converters of all the body types supported by the service, each of them being
+/// accepted both as content and accept type.
+fn with_converters(builder: BackEndHandlerBuilder) -> BackEndHandlerBuilder {
+    builder
+        .with_converter(Box::from(ProtobufConverter {}))
+        .with_content_type(BodyType::Protobuf)
+        .with_accept_type(BodyType::Protobuf)
 }
 
 fn build_providers(
     configs: &[ProviderConfig],
     kim_factorys: HashMap<String, KeyInfoManagerFactory>,
-) -> Result<Vec<(ProviderId, Provider)>> {
+    recovery_interval: Option<Duration>,
+    existing_providers: &mut HashMap<String, (ProviderId, Provider)>,
+) -> Result<Vec<(String, ProviderId, Provider)>> {
     let mut providers = Vec::new();
     let mut provider_names = HashSet::new();
+    let mut tpm_tctis = HashSet::new();
+    let mut single_instance_types = HashSet::new();
+    let mut provider_types = HashSet::new();
     for config in configs {
         // Check for duplicate providers.
         let provider_id = config.provider_id();
@@ -248,6 +554,54 @@ fn build_providers(
         }
         let _ = provider_names.insert(provider_name.clone());
 
+        // Several TPM providers can only share a TCTI if it goes through a resource manager.
+        if let ProviderConfig::Tpm { tcti, .. } = config {
+            if !tcti.starts_with("tabrmd")
+                && !tcti.starts_with("device:/dev/tpmrm")
+                && !tpm_tctis.insert(tcti.clone())
+            {
+                error!("The TCTI {} is used by several TPM providers but does not go through a resource manager.", tcti);
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "TPM providers share a TCTI without resource manager",
+                )
+                .into());
+            }
+        }
+
+        // All the Mbed Crypto providers would share the same PSA key store, and the CryptoAuthLib
+        // library can only be initialised once per process.
+        if matches!(
+            config,
+            ProviderConfig::MbedCrypto { .. } | ProviderConfig::CryptoAuthLib { .. }
+        ) && !single_instance_types.insert(provider_id)
+        {
+            error!(
+                "Only one provider of type {} can be declared, {} is the second one.",
+                provider_id, provider_name
+            );
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "several providers of a single-instance type",
+            )
+            .into());
+        }
+
+        if !provider_types.insert(provider_id) {
+            warn!(
+                "Provider {} is not the first provider of type {}: requests addressed with that \
+                provider ID go to the first one. It is only reachable by name through the HTTP \
+                gateway or with automatic provider selection.",
+                provider_name, provider_id
+            );
+        }
+
+        if let Some((provider_id, provider)) = existing_providers.get(&provider_name) {
+            info!("Reusing the existing provider {}.", provider_name);
+            providers.push((provider_name, *provider_id, provider.clone()));
+            continue;
+        }
+
         let kim_factory = match kim_factorys.get(config.key_info_manager()) {
             Some(kim_factory) => kim_factory,
             None => {
@@ -262,7 +616,9 @@ fn build_providers(
                 .into());
             }
         };
-        // The safety is checked by the fact that only one instance per provider type is enforced.
+        // The safety is checked by the fact that only one instance of the Mbed Crypto and
+        // CryptoAuthLib providers is enforced, and that TPM providers do not share a TCTI unless it
+        // goes through a resource manager.
         let provider = match unsafe { get_provider(config, kim_factory) } {
             Ok(None) => {
                 warn!("Provider {} is skipped.", provider_id);
@@ -277,12 +633,38 @@ fn build_providers(
                 return Err(Error::new(ErrorKind::Other, "failed to create provider").into());
             }
         };
-        providers.push((provider_id, provider));
+        let provider = match recovery_interval {
+            Some(recovery_interval) => {
+                let supervised = Arc::new(SupervisedProvider::new(
+                    provider_name.clone(),
+                    provider,
+                    provider_factory(config.clone(), kim_factory.clone()),
+                ));
+                SupervisedProvider::supervise(&supervised, recovery_interval);
+                supervised
+            }
+            None => provider,
+        };
+        let _ = existing_providers.insert(provider_name.clone(), (provider_id, provider.clone()));
+        providers.push((provider_name, provider_id, provider));
     }
 
     Ok(providers)
 }
 
+/// Function creating the provider again from its configuration, to re-initialise it after a
+/// failure.
+fn provider_factory(config: ProviderConfig, kim_factory: KeyInfoManagerFactory) -> ProviderFactory {
+    Box::new(move || {
+        // The safety is checked by the fact that the failed instance of the provider is dropped
+        // before a new one is created.
+        match unsafe { get_provider(&config, &kim_factory) }? {
+            Some(provider) => Ok(provider),
+            None => Err(Error::new(ErrorKind::Other, "the provider was skipped").into()),
+        }
+    })
+}
+
 // This cfg_attr is used to allow the fact that key_info_manager is not used when there is no
 // providers.
 #[cfg_attr(
@@ -483,23 +865,32 @@ fn build_authenticators(config: &AuthenticatorConfig) -> Result<Vec<(AuthType, A
         #[cfg(feature = "direct-authenticator")]
         AuthenticatorConfig::Direct { admins } => authenticators.push((
             AuthType::Direct,
-            Box::from(DirectAuthenticator::new(
+            Arc::new(DirectAuthenticator::new(
                 admins.as_ref().cloned().unwrap_or_default(),
             )),
         )),
         #[cfg(feature = "unix-peer-credentials-authenticator")]
-        AuthenticatorConfig::UnixPeerCredentials { admins } => authenticators.push((
+        AuthenticatorConfig::UnixPeerCredentials {
+            admins,
+            group_identities,
+            admin_groups,
+        } => authenticators.push((
             AuthType::UnixPeerCredentials,
-            Box::from(UnixPeerCredentialsAuthenticator::new(
-                admins.as_ref().cloned().unwrap_or_default(),
-            )),
+            Arc::new(
+                UnixPeerCredentialsAuthenticator::new(admins.as_ref().cloned().unwrap_or_default())
+                    .with_group_identities(group_identities.as_ref().cloned().unwrap_or_default())
+                    .with_admin_groups(admin_groups.as_ref().cloned().unwrap_or_default()),
+            ),
         )),
         #[cfg(feature = "jwt-svid-authenticator")]
         AuthenticatorConfig::JwtSvid {
             workload_endpoint,
             admins,
+            offline_validation,
+            trust_bundles,
+            trust_bundle_refresh_interval,
         } => {
-            let jwt_svid_authenticator = match JwtSvidAuthenticator::new(
+            let mut jwt_svid_authenticator = match JwtSvidAuthenticator::new(
                 workload_endpoint.to_string(),
                 admins.as_ref().cloned().unwrap_or_default(),
             ) {
@@ -512,12 +903,70 @@ fn build_authenticators(config: &AuthenticatorConfig) -> Result<Vec<(AuthType, A
                     .into())
                 }
             };
-            authenticators.push((AuthType::JwtSvid, Box::from(jwt_svid_authenticator)))
+            if offline_validation.unwrap_or(false) {
+                jwt_svid_authenticator = match jwt_svid_authenticator.with_offline_validation(
+                    trust_bundles.as_ref().cloned().unwrap_or_default(),
+                    Duration::from_secs(
+                        trust_bundle_refresh_interval
+                            .unwrap_or(DEFAULT_TRUST_BUNDLE_REFRESH_INTERVAL),
+                    ),
+                ) {
+                    Some(authenticator) => authenticator,
+                    None => {
+                        return Err(Error::new(
+                            ErrorKind::Other,
+                            "can not load the JWT trust bundles",
+                        )
+                        .into())
+                    }
+                };
+            }
+            authenticators.push((AuthType::JwtSvid, Arc::new(jwt_svid_authenticator)))
         }
+        #[cfg(feature = "jwt-authenticator")]
+        AuthenticatorConfig::Jwt {
+            issuers,
+            audience,
+            name_claim,
+            admins,
+        } => authenticators.push((
+            AuthType::Jwt,
+            Arc::new(JwtAuthenticator::new(
+                issuers,
+                audience.clone(),
+                name_claim.clone(),
+                admins.as_ref().cloned().unwrap_or_default(),
+            )?),
+        )),
+        #[cfg(feature = "peer-executable-authenticator")]
+        AuthenticatorConfig::PeerExecutable {
+            admins,
+            hash_executable,
+        } => authenticators.push((
+            AuthType::UnixPeerCredentials,
+            Arc::new(PeerExecutableAuthenticator::new(
+                admins.as_ref().cloned().unwrap_or_default(),
+                hash_executable.unwrap_or(false),
+            )),
+        )),
+        #[cfg(feature = "cgroup-authenticator")]
+        AuthenticatorConfig::Cgroup {
+            admins,
+            cgroup_patterns,
+        } => authenticators.push((
+            AuthType::UnixPeerCredentials,
+            Arc::new(CgroupAuthenticator::new(
+                admins.as_ref().cloned().unwrap_or_default(),
+                cgroup_patterns,
+            )?),
+        )),
         #[cfg(not(all(
             feature = "direct-authenticator",
             feature = "unix-peer-credentials-authenticator",
             feature = "jwt-svid-authenticator",
+            feature = "jwt-authenticator",
+            feature = "peer-executable-authenticator",
+            feature = "cgroup-authenticator",
         )))]
         _ => {
             error!(
@@ -530,3 +979,114 @@ fn build_authenticators(config: &AuthenticatorConfig) -> Result<Vec<(AuthType, A
 
     Ok(authenticators)
 }
+
+#[cfg(test)]
+mod test {
+    use super::ServiceBuilder;
+    use crate::utils::config::ServiceConfig;
+
+    fn service_config(socket_path: &str, key_info_manager_path: &str) -> ServiceConfig {
+        toml::from_str(&format!(
+            r#"
+            [core_settings]
+
+            [listener]
+            listener_type = "DomainSocket"
+            timeout = 200
+            socket_path = "{}"
+
+            [authenticator]
+            auth_type = "Direct"
+
+            [[key_manager]]
+            name = "on-disk-manager"
+            manager_type = "OnDisk"
+            store_path = "{}"
+
+            [[provider]]
+            provider_type = "MbedCrypto"
+            key_info_manager = "on-disk-manager"
+            "#,
+            socket_path, key_info_manager_path
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn unchanged_listener_is_kept() {
+        let socket_path = std::env::temp_dir().join("parsec-reload-test.sock");
+        let config = service_config(socket_path.to_str().unwrap(), "/tmp/mappings");
+        let listeners = ServiceBuilder::start_listeners(&config.listener).unwrap();
+        let listener_fd = listeners[0].as_raw_fd();
+
+        let listeners =
+            ServiceBuilder::restart_listeners(&config.listener, listeners, &config.listener)
+                .unwrap();
+        assert_eq!(listeners[0].as_raw_fd(), listener_fd);
+        assert!(socket_path.exists());
+
+        drop(listeners);
+        let _ = std::fs::remove_file(socket_path);
+    }
+
+    #[cfg(feature = "mbed-crypto-provider")]
+    mod providers {
+        use super::super::build_providers;
+        use super::service_config;
+        use crate::providers::core::ProviderBuilder as CoreProviderBuilder;
+        use crate::utils::config::ProviderConfig;
+        use crate::utils::ServiceComponents;
+        use parsec_interface::requests::ProviderId;
+        use std::collections::HashMap;
+        use std::sync::Arc;
+
+        fn components_with_provider() -> ServiceComponents {
+            let mut components = ServiceComponents::default();
+            let provider = CoreProviderBuilder::new()
+                .with_wire_protocol_version(0, 1)
+                .build()
+                .unwrap();
+            let _ = components.providers.insert(
+                "mbed-crypto-provider".to_string(),
+                (ProviderId::MbedCrypto, Arc::new(provider)),
+            );
+            components
+        }
+
+        #[test]
+        fn unchanged_provider_is_kept() {
+            let old_config = service_config("/tmp/parsec.sock", "/tmp/mappings");
+            let new_config = service_config("/tmp/parsec-other.sock", "/tmp/mappings");
+            let mut components = components_with_provider();
+
+            assert!(!components.retain_unchanged(&old_config, &new_config));
+            assert!(components.providers.contains_key("mbed-crypto-provider"));
+        }
+
+        #[test]
+        fn provider_with_changed_key_info_manager_is_dropped() {
+            let old_config = service_config("/tmp/parsec.sock", "/tmp/mappings");
+            let new_config = service_config("/tmp/parsec.sock", "/tmp/other-mappings");
+            let mut components = components_with_provider();
+
+            assert!(components.retain_unchanged(&old_config, &new_config));
+            assert!(components.providers.is_empty());
+        }
+
+        #[test]
+        fn second_mbed_crypto_provider_is_rejected() {
+            let config = service_config("/tmp/parsec.sock", "/tmp/mappings");
+            let mut configs = config.provider.unwrap();
+            let mut second = configs[0].clone();
+            if let ProviderConfig::MbedCrypto { name, .. } = &mut second {
+                *name = Some("other-mbed-crypto-provider".to_string());
+            }
+            configs.push(second);
+            let mut components = components_with_provider();
+
+            assert!(
+                build_providers(&configs, HashMap::new(), None, &mut components.providers).is_err()
+            );
+        }
+    }
+}